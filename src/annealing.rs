@@ -2,8 +2,8 @@
 /// Math is shamelessly taken from: http://mkweb.bcgsc.ca/carpalx/?simulated_annealing
 /// This code is written to be generic and can be reused for other applications.
 use rand::Rng;
-use rand::StdRng;
 use std::f64;
+use std::io::{self, Write};
 use std::ops::Range;
 
 // These values are taken from Carpalx, with T0 adjusted for the scale that our
@@ -12,11 +12,14 @@ const T0: f64 = 1.5; // Scale so that dE_max/T0 ~= 1
 const P0: f64 = 1.0;
 const N: usize = 15000;
 
+// Default number of iterations over which the acceptance rate is averaged.
+pub const ACCEPTANCE_WINDOW: usize = 100;
+
 const K:  f64   = 10.0;
 const KN: f64   = K / (N as f64);
 
 // T(i) = T0 exp(-ik/N)
-fn temperature(i: usize) -> f64 {
+pub fn temperature(i: usize) -> f64 {
     // T0 * (1.0 - (i as f64) / (N as f64))
     T0 * f64::exp(-(i as f64) * KN)
 }
@@ -28,7 +31,7 @@ fn cutoff_p(de: f64, i: usize) -> f64 {
 }
 
 // For positive dE, accept if r < p_dE where r ~ Uniform(0, 1)
-pub fn accept_transition(de: f64, i: usize, rng: &mut StdRng) -> bool {
+pub fn accept_transition<R: Rng>(de: f64, i: usize, rng: &mut R) -> bool {
     if de <= 0.0 {
         true
     } else {
@@ -41,3 +44,137 @@ pub fn accept_transition(de: f64, i: usize, rng: &mut StdRng) -> bool {
 pub fn get_simulation_range() -> Range<usize> {
     1..(N + 1)
 }
+
+#[derive(Clone)]
+pub struct Step {
+    pub iteration: usize,
+    pub temperature: f64,
+    pub penalty: f64,
+    pub best_penalty: f64,
+    pub accepted: bool,
+}
+
+/// Record of every iteration of a simulation, used for convergence diagnostics.
+#[derive(Clone, Default)]
+pub struct Trajectory {
+    pub steps: Vec<Step>,
+}
+
+impl Trajectory {
+    pub fn new() -> Trajectory {
+        Trajectory { steps: Vec::new() }
+    }
+
+    pub fn record(&mut self, iteration: usize, penalty: f64, best_penalty: f64, accepted: bool) {
+        self.steps.push(Step {
+            iteration,
+            temperature: temperature(iteration),
+            penalty,
+            best_penalty,
+            accepted,
+        });
+    }
+
+    // Fraction of accepted transitions among the last `window` iterations, for
+    // every recorded iteration.
+    pub fn acceptance_rate(&self, window: usize) -> Vec<f64> {
+        let mut accepted = 0;
+        (0..self.steps.len())
+            .map(|i| {
+                if self.steps[i].accepted {
+                    accepted += 1;
+                }
+                if i >= window && self.steps[i - window].accepted {
+                    accepted -= 1;
+                }
+                accepted as f64 / (i + 1).min(window) as f64
+            })
+            .collect()
+    }
+
+    pub fn write_csv_header<W: Write>(writer: &mut W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "run,iteration,temperature,penalty,best_penalty,accepted,acceptance_rate"
+        )
+    }
+
+    // Writes every `every`th iteration (and always the last one) as CSV rows.
+    pub fn write_csv<W: Write>(
+        &self,
+        writer: &mut W,
+        run: usize,
+        window: usize,
+        every: usize,
+    ) -> io::Result<()> {
+        let rate = self.acceptance_rate(window);
+        for (i, step) in self.steps.iter().enumerate() {
+            if (i + 1) % every != 0 && i + 1 != self.steps.len() {
+                continue;
+            }
+            writeln!(
+                writer,
+                "{},{},{:.6},{:.6},{:.6},{},{:.4}",
+                run,
+                step.iteration,
+                step.temperature,
+                step.penalty,
+                step.best_penalty,
+                step.accepted as u8,
+                rate[i]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trajectory of `accepted.len()` iterations with the given acceptances.
+    fn trajectory(accepted: &[bool]) -> Trajectory {
+        let mut trajectory = Trajectory::new();
+        for (i, &accepted) in accepted.iter().enumerate() {
+            trajectory.record(i + 1, 2.0 - i as f64 / 10.0, 1.5, accepted);
+        }
+        trajectory
+    }
+
+    #[test]
+    fn acceptance_rate() {
+        let trajectory = trajectory(&[true, false, false, true, true, false]);
+        // Averaged over the iterations so far until the window is full.
+        assert_eq!(trajectory.acceptance_rate(3), vec![1.0, 0.5, 1.0 / 3.0, 1.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0]);
+        assert_eq!(trajectory.acceptance_rate(1), vec![1.0, 0.0, 0.0, 1.0, 1.0, 0.0]);
+        assert_eq!(trajectory.acceptance_rate(100)[5], 0.5);
+        assert!(Trajectory::new().acceptance_rate(3).is_empty());
+    }
+
+    #[test]
+    fn csv() {
+        let trajectory = trajectory(&[true, false, false, true, true]);
+        let mut out = Vec::new();
+        Trajectory::write_csv_header(&mut out).unwrap();
+        trajectory.write_csv(&mut out, 2, 2, 2).unwrap();
+        let expected = format!(
+            "run,iteration,temperature,penalty,best_penalty,accepted,acceptance_rate\n\
+             2,2,{:.6},1.900000,1.500000,0,0.5000\n\
+             2,4,{:.6},1.700000,1.500000,1,0.5000\n\
+             2,5,{:.6},1.600000,1.500000,1,1.0000\n",
+            temperature(2),
+            temperature(4),
+            temperature(5)
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn temperature_falls() {
+        assert_eq!(temperature(0), T0);
+        assert!(temperature(N) < temperature(N / 2) && temperature(N / 2) < T0);
+        let mut rng = crate::checkpoint::RunRng::new(0);
+        assert!(accept_transition(-1.0, 1, &mut rng) && accept_transition(0.0, N, &mut rng));
+        assert!(!accept_transition(1000.0, N, &mut rng));
+    }
+}
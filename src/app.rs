@@ -1,11 +1,22 @@
 use clap::{App, Arg, ArgMatches};
+use regex::Regex;
+use std::ffi::OsString;
 use std::path::PathBuf;
 
-use crate::layout;
-use crate::layout::Layout;
+use crate::annealing;
+use crate::export::{ExportOptions, Format};
+use crate::import;
+use crate::layout::{KeyMap, Layout};
+use crate::layout_file::{self, LayoutInfo};
+use crate::penalty;
+use crate::preprocess::{self, Code, Preprocess};
+use crate::registry::Registry;
+use crate::sampling::Sampling;
+use crate::svg::{Metric, SvgOptions};
 use crate::Result;
 
 const DEFAULT_SWAPS: usize = 2;
+const DEFAULT_CHECKPOINT_EVERY: usize = 1000;
 
 #[derive(Debug)]
 pub enum Command {
@@ -13,6 +24,11 @@ pub enum Command {
     RunRefs,
     Refine,
     Analyze,
+    Export,
+    Validate,
+    ListLayouts,
+    CorpusBuild,
+    CorpusStats,
 }
 
 #[derive(Debug)]
@@ -20,10 +36,33 @@ pub struct Config {
     pub debug: bool,
     pub swaps: usize,
     pub command: Command,
-    pub corpus_path: PathBuf,
+    // Corpora with their weights.
+    pub corpora: Vec<(PathBuf, f64)>,
+    // Layout files, directories and globs given to `analyze`.
+    pub inputs: Vec<String>,
+    // Read the files of a corpus directory in parallel.
+    pub parallel: bool,
+    pub preprocess: Preprocess,
+    // Held-out part and bootstrapping of text corpora.
+    pub sampling: Sampling,
+    // Number of keys that penalties see, i.e. the length of corpus n-grams.
+    pub context: usize,
     pub repetition: usize,
     pub layout: Layout,
+    pub layout_info: LayoutInfo,
+    pub registry: Registry,
+    // Keys that `run` and `refine` may swap.
+    pub shuffle_mask: KeyMap<bool>,
     pub output: Option<PathBuf>,
+    pub svg: Option<SvgOptions>,
+    pub report: Option<PathBuf>,
+    pub trace: Option<PathBuf>,
+    pub trace_window: usize,
+    pub trace_every: usize,
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_every: usize,
+    pub resume: bool,
+    pub export: Option<ExportOptions>,
 }
 
 fn print_usage_and_exit(matches: &ArgMatches) -> ! {
@@ -31,8 +70,82 @@ fn print_usage_and_exit(matches: &ArgMatches) -> ! {
     std::process::exit(1);
 }
 
+// A corpus given as `PATH=WEIGHT`, or as `PATH` for a weight of 1.
+fn parse_corpus(s: &str) -> Result<(PathBuf, f64)> {
+    match s.rsplit_once('=') {
+        Some((path, weight)) => match str::parse::<f64>(weight) {
+            Ok(weight) if weight.is_finite() && weight > 0.0 => Ok((PathBuf::from(path), weight)),
+            _ => Err(format!("Invalid option for '--corpus': '{}'", s).into()),
+        },
+        None => Ok((PathBuf::from(s), 1.0)),
+    }
+}
+
+fn parse_code(matches: &ArgMatches) -> Result<Option<Code>> {
+    let weight = |name: &str| match matches.value_of(name) {
+        Some(s) => match str::parse::<f64>(s) {
+            Ok(weight) if weight.is_finite() && weight >= 0.0 => Ok(weight),
+            _ => Err(format!("Invalid option for '--{}': '{}'", name, s)),
+        },
+        None => Ok(1.0),
+    };
+    if !matches.is_present("code") {
+        for name in &["code-ext", "autopairs", "identifier-weight", "punctuation-weight"] {
+            if matches.is_present(name) {
+                return Err(format!("'--{}' requires '--code'", name).into());
+            }
+        }
+        return Ok(None);
+    }
+    let extensions = matches.value_of("code-ext").unwrap_or(preprocess::DEFAULT_CODE_EXTENSIONS);
+    Ok(Some(Code {
+        extensions: extensions
+            .split(',')
+            .map(|e| e.trim().trim_start_matches('.').to_string())
+            .filter(|e| !e.is_empty())
+            .collect(),
+        autopairs: matches.is_present("autopairs"),
+        identifier_weight: weight("identifier-weight")?,
+        punctuation_weight: weight("punctuation-weight")?,
+    }))
+}
+
+fn parse_sampling(matches: &ArgMatches) -> Result<Sampling> {
+    let holdout = match matches.value_of("holdout") {
+        Some(s) => match str::parse::<f64>(s) {
+            Ok(fraction) if fraction > 0.0 && fraction < 1.0 => fraction,
+            _ => return Err(format!("Invalid option for '--holdout': '{}'", s).into()),
+        },
+        None => 0.0,
+    };
+    let bootstrap = match matches.value_of("bootstrap") {
+        Some(s) => match str::parse::<usize>(s) {
+            Ok(count) if count > 0 => count,
+            _ => return Err(format!("Invalid option for '--bootstrap': '{}'", s).into()),
+        },
+        None => 0,
+    };
+    let by_document = match matches.value_of("split-by") {
+        Some(_) if holdout == 0.0 && bootstrap == 0 => {
+            return Err("'--split-by' requires '--holdout' or '--bootstrap'".into())
+        }
+        Some("line") | None => false,
+        Some("document") => true,
+        Some(s) => return Err(format!("Invalid option for '--split-by': '{}'", s).into()),
+    };
+    Ok(Sampling { holdout, by_document, bootstrap })
+}
+
 impl Config {
     pub fn from_env() -> Result<Config> {
+        Config::from_args(std::env::args_os())
+    }
+
+    pub fn from_args<I, T>(args: I) -> Result<Config>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
         let matches = App::new("keygen")
             .arg(Arg::with_name("debug").long("debug").short("d"))
             .arg(
@@ -50,9 +163,22 @@ impl Config {
             .arg(
                 Arg::with_name("corpus")
                     .index(2)
-                    .required(true)
                     .value_name("PATH"),
             )
+            .arg(
+                Arg::with_name("inputs")
+                    .index(3)
+                    .multiple(true)
+                    .value_name("LAYOUTS"),
+            )
+            .arg(
+                Arg::with_name("corpora")
+                    .long("corpus")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name("PATH=WEIGHT"),
+            )
             .arg(
                 Arg::with_name("repetitions")
                     .long("repititions")
@@ -67,6 +193,18 @@ impl Config {
                     .takes_value(true)
                     .value_name("PATH"),
             )
+            .arg(
+                Arg::with_name("layout-format")
+                    .long("layout-format")
+                    .takes_value(true)
+                    .value_name("FORMAT"),
+            )
+            .arg(
+                Arg::with_name("layouts-dir")
+                    .long("layouts-dir")
+                    .takes_value(true)
+                    .value_name("DIR"),
+            )
             .arg(
                 Arg::with_name("output")
                 .long("output")
@@ -74,26 +212,225 @@ impl Config {
                 .takes_value(true)
                 .value_name("PATH")
             )
-            .get_matches();
-
-        let layout = match matches.value_of("layout") {
-            None => crate::penalty::INIT_LAYOUT.clone(),
-            Some(path) => match path {
-                "colemak" => layout::COLEMAK_LAYOUT.clone(),
-                "colemak-dh" => layout::COLEMAK_DH_LAYOUT.clone(),
-                "dvorak" => layout::DVORAK_LAYOUT.clone(),
-                "mtgap" => layout::MTGAP_LAYOUT.clone(),
-                "qwerty" => layout::QWERTY_LAYOUT.clone(),
-                "rsthd" => layout::RSTHD_LAYOUT.clone(),
-                "workman" => layout::WORKMAN_LAYOUT.clone(),
-                _ => {
-                    let s = std::fs::read_to_string(&path)?;
-                    Layout::from_string(&s)
-                        .ok_or(format!("File {} does not contain a valid layout.", path))?
+            .arg(
+                Arg::with_name("svg")
+                    .long("svg")
+                    .takes_value(true)
+                    .value_name("PATH"),
+            )
+            .arg(
+                Arg::with_name("svg-metric")
+                    .long("svg-metric")
+                    .takes_value(true)
+                    .value_name("usage|penalty"),
+            )
+            .arg(Arg::with_name("svg-fingers").long("svg-fingers"))
+            .arg(Arg::with_name("parallel").long("parallel"))
+            .arg(Arg::with_name("fold-case").long("fold-case"))
+            .arg(Arg::with_name("collapse-whitespace").long("collapse-whitespace"))
+            .arg(Arg::with_name("strip-digits").long("strip-digits"))
+            .arg(
+                Arg::with_name("newline")
+                    .long("newline")
+                    .takes_value(true)
+                    .value_name("KEY"),
+            )
+            .arg(
+                Arg::with_name("tab")
+                    .long("tab")
+                    .takes_value(true)
+                    .value_name("KEY"),
+            )
+            .arg(
+                Arg::with_name("drop-lines")
+                    .long("drop-lines")
+                    .takes_value(true)
+                    .value_name("REGEX"),
+            )
+            .arg(Arg::with_name("latency-weights").long("latency-weights"))
+            .arg(
+                Arg::with_name("context")
+                    .long("context")
+                    .takes_value(true)
+                    .value_name("KEYS"),
+            )
+            .arg(
+                Arg::with_name("holdout")
+                    .long("holdout")
+                    .takes_value(true)
+                    .value_name("FRACTION"),
+            )
+            .arg(
+                Arg::with_name("split-by")
+                    .long("split-by")
+                    .takes_value(true)
+                    .value_name("line|document"),
+            )
+            .arg(
+                Arg::with_name("bootstrap")
+                    .long("bootstrap")
+                    .takes_value(true)
+                    .value_name("COUNT"),
+            )
+            .arg(Arg::with_name("code").long("code"))
+            .arg(
+                Arg::with_name("code-ext")
+                    .long("code-ext")
+                    .takes_value(true)
+                    .value_name("EXTENSIONS"),
+            )
+            .arg(Arg::with_name("autopairs").long("autopairs"))
+            .arg(
+                Arg::with_name("identifier-weight")
+                    .long("identifier-weight")
+                    .takes_value(true)
+                    .value_name("WEIGHT"),
+            )
+            .arg(
+                Arg::with_name("punctuation-weight")
+                    .long("punctuation-weight")
+                    .takes_value(true)
+                    .value_name("WEIGHT"),
+            )
+            .arg(
+                Arg::with_name("trace")
+                    .long("trace")
+                    .takes_value(true)
+                    .value_name("PATH"),
+            )
+            .arg(
+                Arg::with_name("trace-window")
+                    .long("trace-window")
+                    .takes_value(true)
+                    .value_name("COUNT"),
+            )
+            .arg(
+                Arg::with_name("trace-every")
+                    .long("trace-every")
+                    .takes_value(true)
+                    .value_name("COUNT"),
+            )
+            .arg(
+                Arg::with_name("checkpoint")
+                    .long("checkpoint")
+                    .takes_value(true)
+                    .value_name("PATH"),
+            )
+            .arg(
+                Arg::with_name("checkpoint-every")
+                    .long("checkpoint-every")
+                    .takes_value(true)
+                    .value_name("COUNT"),
+            )
+            .arg(
+                Arg::with_name("resume")
+                    .long("resume")
+                    .requires("checkpoint"),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .value_name("FORMAT"),
+            )
+            .arg(
+                Arg::with_name("board-macro")
+                    .long("board-macro")
+                    .takes_value(true)
+                    .value_name("NAME"),
+            )
+            .arg(
+                Arg::with_name("key-order")
+                    .long("key-order")
+                    .takes_value(true)
+                    .value_name("POSITIONS"),
+            )
+            .arg(
+                Arg::with_name("report")
+                    .long("report")
+                    .takes_value(true)
+                    .value_name("PATH"),
+            )
+            .get_matches_from(args);
+
+        let layout_format = match matches.value_of("layout-format") {
+            Some(s) => Some(
+                import::Format::from_name(s)
+                    .ok_or(format!("Invalid option for '--layout-format': '{}'", s))?,
+            ),
+            None => None,
+        };
+
+        let registry = Registry::load(matches.value_of("layouts-dir").map(std::path::Path::new))?;
+        let (layout, layout_info) = match matches.value_of("layout") {
+            None => (penalty::INIT_LAYOUT.clone(), LayoutInfo::default()),
+            Some(name) => match registry.get(name) {
+                Some(entry) => (entry.layout.clone(), entry.info.clone()),
+                None => {
+                    let s = std::fs::read_to_string(name).map_err(|e| {
+                        format!("'{}' is neither a known layout nor a readable file: {}", name, e)
+                    })?;
+                    import::parse(&s, layout_format).map_err(|e| {
+                        format!("File {} does not contain a valid layout: {}", name, e)
+                    })?
                 }
             },
         };
 
+        let command = match (matches.value_of("command").unwrap(), matches.value_of("corpus")) {
+            ("run", _) => Command::Run,
+            ("run-refs", _) => Command::RunRefs,
+            ("refine", _) => Command::Refine,
+            ("analyze", _) => Command::Analyze,
+            ("export", _) => Command::Export,
+            ("validate", _) => Command::Validate,
+            ("list-layouts", _) => Command::ListLayouts,
+            ("corpus", Some("build")) => Command::CorpusBuild,
+            ("corpus-stats", _) => Command::CorpusStats,
+            _ => print_usage_and_exit(&matches),
+        };
+
+        // Only `run` anneals.
+        if !matches!(command, Command::Run) {
+            for name in &["trace", "trace-window", "trace-every"] {
+                if matches.is_present(name) {
+                    return Err(format!("'--{}' is only supported by 'run'", name).into());
+                }
+            }
+        }
+
+        let mut inputs: Vec<String> = matches
+            .values_of("inputs")
+            .map(|values| values.map(str::to_string).collect())
+            .unwrap_or_default();
+        let mut corpora = match matches.values_of("corpora") {
+            Some(values) => values.map(parse_corpus).collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        let mut corpus = matches.value_of("corpus");
+        if let Command::CorpusBuild = command {
+            // `corpus build` reads all texts after its action.
+            corpora.extend(inputs.drain(..).map(|path| (PathBuf::from(path), 1.0)));
+            corpus = None;
+        }
+        match corpus {
+            Some(path) if corpora.is_empty() => corpora.push((PathBuf::from(path), 1.0)),
+            // With '--corpus', the first positional argument after the command
+            // is a layout of `analyze`, or the corpus that `corpus-stats`
+            // compares with, rather than the corpus.
+            Some(path) if matches!(command, Command::Analyze | Command::CorpusStats) => {
+                inputs.insert(0, path.to_string())
+            }
+            Some(_) => return Err("Pass the corpus either as an argument or with '--corpus'".into()),
+            None => {}
+        }
+
+        // Pinned keys are excluded from swapping on top of the static mask.
+        let mut shuffle_mask = penalty::LAYOUT_MASK.clone();
+        for pos in &layout_info.pinned {
+            shuffle_mask.0[*pos] = false;
+        }
+
         Ok(Config {
             debug: matches.is_present("debug"),
 
@@ -109,19 +446,136 @@ impl Config {
                 None => 1,
             },
 
-            command: match matches.value_of("command").unwrap() {
-                "run" => Command::Run,
-                "run-refs" => Command::RunRefs,
-                "refine" => Command::Refine,
-                "analyze" => Command::Analyze,
-                _ => print_usage_and_exit(&matches),
+            command,
+
+            corpora,
+
+            inputs,
+
+            parallel: matches.is_present("parallel"),
+
+            preprocess: Preprocess {
+                fold_case: matches.is_present("fold-case"),
+                collapse_whitespace: matches.is_present("collapse-whitespace"),
+                newline: match matches.value_of("newline") {
+                    Some(s) => Some(
+                        layout_file::unescape(s).ok_or(format!("Invalid option for '--newline': '{}'", s))?,
+                    ),
+                    None => None,
+                },
+                tab: match matches.value_of("tab") {
+                    Some(s) => Some(layout_file::unescape(s).ok_or(format!("Invalid option for '--tab': '{}'", s))?),
+                    None => None,
+                },
+                strip_digits: matches.is_present("strip-digits"),
+                drop_lines: match matches.value_of("drop-lines") {
+                    Some(s) => Some(
+                        Regex::new(s).map_err(|e| format!("Invalid option for '--drop-lines': '{}': {}", s, e))?,
+                    ),
+                    None => None,
+                },
+                code: parse_code(&matches)?,
+                latency_weights: matches.is_present("latency-weights"),
             },
 
-            corpus_path: PathBuf::from(matches.value_of("corpus").unwrap()),
+            sampling: parse_sampling(&matches)?,
+
+            context: match matches.value_of("context") {
+                Some(s) => match str::parse::<usize>(s) {
+                    Ok(keys) if (penalty::DEFAULT_CONTEXT..=penalty::MAX_CONTEXT).contains(&keys) => keys,
+                    _ => return Err(format!("Invalid option for '--context': '{}'", s).into()),
+                },
+                None => penalty::DEFAULT_CONTEXT,
+            },
+
+            output: matches.value_of("output").map(PathBuf::from),
+
+            svg: match matches.value_of("svg") {
+                Some(path) => Some(SvgOptions {
+                    path: PathBuf::from(path),
+                    metric: match matches.value_of("svg-metric") {
+                        Some(s) => Metric::from_name(s)
+                            .ok_or(format!("Invalid option for '--svg-metric': '{}'", s))?,
+                        None => Metric::Usage,
+                    },
+                    fingers: matches.is_present("svg-fingers"),
+                }),
+                None => None,
+            },
+
+            report: matches.value_of("report").map(PathBuf::from),
+
+            checkpoint: matches.value_of("checkpoint").map(PathBuf::from),
+
+            checkpoint_every: match matches.value_of("checkpoint-every") {
+                Some(s) => str::parse::<usize>(s)
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or(format!("Invalid option for '--checkpoint-every': '{}'", s))?,
+                None => DEFAULT_CHECKPOINT_EVERY,
+            },
 
-            output: matches.value_of("output").map(|s| PathBuf::from(s)),
+            resume: matches.is_present("resume"),
+
+            export: match matches.value_of("command") {
+                Some("export") => Some(ExportOptions {
+                    format: match matches.value_of("format") {
+                        Some(s) => Format::from_name(s)
+                            .ok_or(format!("Invalid option for '--format': '{}'", s))?,
+                        None => return Err("'export' requires '--format'".into()),
+                    },
+                    board_macro: matches.value_of("board-macro").unwrap_or("LAYOUT").to_string(),
+                    key_order: match matches.value_of("key-order") {
+                        Some(s) => Some(ExportOptions::parse_key_order(s)?),
+                        None => None,
+                    },
+                }),
+                _ => None,
+            },
+
+            trace: matches.value_of("trace").map(PathBuf::from),
+
+            trace_window: match matches.value_of("trace-window") {
+                Some(s) => str::parse::<usize>(s)
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or(format!("Invalid option for '--trace-window': '{}'", s))?,
+                None => annealing::ACCEPTANCE_WINDOW,
+            },
+
+            trace_every: match matches.value_of("trace-every") {
+                Some(s) => str::parse::<usize>(s)
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or(format!("Invalid option for '--trace-every': '{}'", s))?,
+                None => 1,
+            },
 
             layout,
+            layout_info,
+            registry,
+            shuffle_mask,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(args: &[&str]) -> String {
+        match Config::from_args(std::iter::once(&"keygen").chain(args)) {
+            Ok(_) => panic!("{:?} was accepted", args),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn trace_only_for_run() {
+        assert!(Config::from_args(["keygen", "run", "corpus", "--trace", "t.csv", "--trace-every", "10"]).is_ok());
+        for command in &["refine", "analyze", "run-refs"] {
+            assert_eq!(error(&[command, "corpus", "--trace", "t.csv"]), "'--trace' is only supported by 'run'");
+            assert_eq!(error(&[command, "corpus", "--trace-window", "5"]), "'--trace-window' is only supported by 'run'");
+        }
+    }
+}
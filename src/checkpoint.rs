@@ -0,0 +1,471 @@
+/// Checkpoints for resuming interrupted `run` and `refine` invocations, and
+/// graceful handling of SIGINT.
+///
+/// A checkpoint is a plain text file. Layouts are stored as the code points of
+/// both layers so that any character survives the round trip.
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rand::{Rng, SeedableRng, StdRng};
+
+use crate::annealing::Trajectory;
+use crate::app::{Command, Config};
+use crate::layout::Layout;
+use crate::penalty::{self, Corpus};
+use crate::Result;
+
+const MAGIC: &str = "keygen-checkpoint 2";
+
+// Seed of the random number generator at the start of every annealing run.
+const INITIAL_SEED: [usize; 4] = [1, 2, 3, 4];
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs a SIGINT handler that asks running optimisations to stop. A second
+/// SIGINT terminates the process immediately.
+pub fn install_interrupt_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!("\nInterrupted, finishing up. Press Ctrl-C again to quit immediately.");
+    })?;
+    Ok(())
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Random number generator of an annealing run, which counts the numbers it
+/// has drawn so that a resumed run can continue with the same ones.
+pub struct RunRng {
+    rng: StdRng,
+    pub draws: u64,
+}
+
+impl RunRng {
+    /// The generator of a run after `draws` numbers.
+    pub fn new(draws: u64) -> RunRng {
+        let mut rng: StdRng = SeedableRng::from_seed(&INITIAL_SEED[..]);
+        for _ in 0..draws {
+            rng.next_u64();
+        }
+        RunRng { rng, draws }
+    }
+}
+
+// `StdRng` derives everything from its 64 bit numbers, using one per 32 bit
+// number as well.
+impl Rng for RunRng {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        self.rng.next_u64()
+    }
+}
+
+/// State of a single annealing run after `iteration` iterations.
+#[derive(Clone)]
+pub struct RunState {
+    pub iteration: usize,
+    // Numbers drawn from the random number generator so far.
+    pub draws: u64,
+    pub layout: Layout,
+    pub penalty: f64,
+    pub best_layout: Layout,
+    pub best_penalty: f64,
+    pub trajectory: Trajectory,
+}
+
+impl RunState {
+    pub fn new(layout: &Layout, penalty: f64) -> RunState {
+        RunState {
+            iteration: 0,
+            draws: 0,
+            layout: layout.clone(),
+            penalty,
+            best_layout: layout.clone(),
+            best_penalty: penalty,
+            trajectory: Trajectory::new(),
+        }
+    }
+}
+
+// Only ever built once per checkpoint, so the size of the variants is irrelevant.
+#[allow(clippy::large_enum_variant)]
+pub enum Checkpoint {
+    Run {
+        // Best layout and trajectory of every finished repetition.
+        completed: Vec<(Layout, Trajectory)>,
+        current: Option<RunState>,
+    },
+    Refine {
+        // Last finished sweep and the layout it produced.
+        sweep: usize,
+        layout: Layout,
+        sweeps: Vec<f64>,
+    },
+}
+
+/// Hash of everything that has to match for a checkpoint to be resumable.
+pub fn config_hash(config: &Config, corpus: &Corpus) -> u64 {
+    let mut key = format!(
+        "{:?} {} {} {:?} {}",
+        config.command,
+        config.swaps,
+        config.repetition,
+        config.corpora,
+        corpus.len
+    );
+    key.push_str(&encode_layout(&config.layout));
+    if !config.layout_info.pinned.is_empty() {
+        key.push_str(&format!(" pinned {:?}", config.layout_info.pinned));
+    }
+    if !config.preprocess.is_identity() {
+        key.push_str(&format!(" preprocess {}", config.preprocess));
+    }
+    if config.context != penalty::DEFAULT_CONTEXT {
+        key.push_str(&format!(" context {}", config.context));
+    }
+    if config.sampling.holdout > 0.0 {
+        key.push_str(&format!(" holdout {} {}", config.sampling.holdout, config.sampling.by_document));
+    }
+
+    // FNV-1a, which unlike `DefaultHasher` is stable across Rust versions.
+    key.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Checkpoint {
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P, hash: u64) -> Result<()> {
+        let mut out = format!("{}\nconfig {:016x}\n", MAGIC, hash);
+        match self {
+            Checkpoint::Run { completed, current } => {
+                out.push_str("command run\n");
+                for (layout, trajectory) in completed {
+                    out.push_str("completed\n");
+                    out.push_str(&format!("best_layout {}\n", encode_layout(layout)));
+                    encode_trajectory(&mut out, trajectory);
+                }
+                if let Some(state) = current {
+                    out.push_str("current\n");
+                    out.push_str(&format!("iteration {}\n", state.iteration));
+                    out.push_str(&format!("draws {}\n", state.draws));
+                    out.push_str(&format!("layout {}\n", encode_layout(&state.layout)));
+                    out.push_str(&format!("penalty {:e}\n", state.penalty));
+                    out.push_str(&format!("best_layout {}\n", encode_layout(&state.best_layout)));
+                    out.push_str(&format!("best_penalty {:e}\n", state.best_penalty));
+                    encode_trajectory(&mut out, &state.trajectory);
+                }
+            }
+            Checkpoint::Refine { sweep, layout, sweeps } => {
+                out.push_str("command refine\n");
+                out.push_str(&format!("sweep {}\n", sweep));
+                out.push_str(&format!("layout {}\n", encode_layout(layout)));
+                out.push_str(&format!(
+                    "sweeps {}\n",
+                    sweeps.iter().map(|p| format!("{:e}", p)).collect::<Vec<_>>().join(",")
+                ));
+            }
+        }
+
+        // Write to a temporary file first so that an interruption while
+        // writing never leaves a truncated checkpoint behind.
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P, config: &Config, hash: u64) -> Result<Checkpoint> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read checkpoint {}: {}", path.display(), e))?;
+        let invalid = || format!("File {} is not a valid checkpoint", path.display());
+        let mut lines = s.lines();
+
+        if lines.next() != Some(MAGIC) {
+            return Err(invalid().into());
+        }
+        let stored_hash = lines
+            .next()
+            .and_then(|l| l.strip_prefix("config "))
+            .and_then(|h| u64::from_str_radix(h, 16).ok())
+            .ok_or_else(invalid)?;
+        if stored_hash != hash {
+            return Err(format!(
+                "Checkpoint {} was written with a different configuration",
+                path.display()
+            )
+            .into());
+        }
+
+        let mut fields = Fields { lines, invalid: &invalid };
+        match (fields.value("command")?, &config.command) {
+            ("run", Command::Run) => {
+                let mut completed = Vec::new();
+                let mut current = None;
+                while let Some(section) = fields.lines.next() {
+                    match section {
+                        "completed" => {
+                            let layout = fields.layout("best_layout")?;
+                            completed.push((layout, fields.trajectory()?));
+                        }
+                        "current" => {
+                            current = Some(RunState {
+                                iteration: fields.parse("iteration")?,
+                                draws: fields.parse("draws")?,
+                                layout: fields.layout("layout")?,
+                                penalty: fields.parse("penalty")?,
+                                best_layout: fields.layout("best_layout")?,
+                                best_penalty: fields.parse("best_penalty")?,
+                                trajectory: fields.trajectory()?,
+                            });
+                        }
+                        _ => return Err(invalid().into()),
+                    }
+                }
+                Ok(Checkpoint::Run { completed, current })
+            }
+            ("refine", Command::Refine) => Ok(Checkpoint::Refine {
+                sweep: fields.parse("sweep")?,
+                layout: fields.layout("layout")?,
+                sweeps: fields
+                    .value("sweeps")?
+                    .split(',')
+                    .map(|s| s.parse().map_err(|_| invalid()))
+                    .collect::<std::result::Result<_, _>>()?,
+            }),
+            (command, _) => Err(format!(
+                "Checkpoint {} belongs to the '{}' command",
+                path.display(),
+                command
+            )
+            .into()),
+        }
+    }
+}
+
+struct Fields<'a, F: Fn() -> String> {
+    lines: std::str::Lines<'a>,
+    invalid: &'a F,
+}
+
+impl<'a, F: Fn() -> String> Fields<'a, F> {
+    fn value(&mut self, name: &str) -> Result<&'a str> {
+        let line = self.lines.next().ok_or_else(self.invalid)?;
+        match line.split_once(' ') {
+            Some((key, value)) if key == name => Ok(value),
+            _ => Err((self.invalid)().into()),
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self, name: &str) -> Result<T> {
+        let value = self.value(name)?;
+        value.parse().map_err(|_| (self.invalid)().into())
+    }
+
+    fn layout(&mut self, name: &str) -> Result<Layout> {
+        let value = self.value(name)?;
+        decode_layout(value).ok_or_else(|| (self.invalid)().into())
+    }
+
+    fn trajectory(&mut self) -> Result<Trajectory> {
+        let len: usize = self.parse("steps")?;
+        let mut trajectory = Trajectory::new();
+        for _ in 0..len {
+            let line = self.lines.next().ok_or_else(self.invalid)?;
+            let mut values = line.split(' ');
+            let mut next = || values.next().ok_or_else(self.invalid);
+            let iteration = next()?.parse().map_err(|_| (self.invalid)())?;
+            let penalty = next()?.parse().map_err(|_| (self.invalid)())?;
+            let best_penalty = next()?.parse().map_err(|_| (self.invalid)())?;
+            let accepted = next()? == "1";
+            trajectory.record(iteration, penalty, best_penalty, accepted);
+        }
+        Ok(trajectory)
+    }
+}
+
+fn encode_trajectory(out: &mut String, trajectory: &Trajectory) {
+    out.push_str(&format!("steps {}\n", trajectory.steps.len()));
+    for step in &trajectory.steps {
+        out.push_str(&format!(
+            "{} {:e} {:e} {}\n",
+            step.iteration, step.penalty, step.best_penalty, step.accepted as u8
+        ));
+    }
+}
+
+fn encode_layout(layout: &Layout) -> String {
+    layout
+        .lower()
+        .iter()
+        .chain(layout.upper().iter())
+        .map(|c| (*c as u32).to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn decode_layout(s: &str) -> Option<Layout> {
+    let chars = s
+        .split(',')
+        .map(|c| c.parse().ok().and_then(std::char::from_u32))
+        .collect::<Option<Vec<char>>>()?;
+    if chars.len() != 72 {
+        return None;
+    }
+    let mut lower = ['\0'; 36];
+    let mut upper = ['\0'; 36];
+    lower.copy_from_slice(&chars[..36]);
+    upper.copy_from_slice(&chars[36..]);
+    Some(Layout::from_layers(lower, upper))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        let mut all = vec!["keygen"];
+        all.extend_from_slice(args);
+        Config::from_args(all).unwrap()
+    }
+
+    fn corpus() -> Corpus {
+        Corpus { len: 1000.0, ..Corpus::default() }
+    }
+
+    fn trajectory(steps: usize) -> Trajectory {
+        let mut trajectory = Trajectory::new();
+        for i in 1..=steps {
+            trajectory.record(i, 1.0 / i as f64, 0.5 / i as f64, i % 2 == 0);
+        }
+        trajectory
+    }
+
+    // Writes a checkpoint, reads it back and checks that it writes the same
+    // file again.
+    fn round_trip(name: &str, checkpoint: &Checkpoint, config: &Config) -> Checkpoint {
+        let dir = std::env::temp_dir().join(format!("keygen-checkpoint-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let hash = config_hash(config, &corpus());
+        checkpoint.write_to_file(dir.join("first"), hash).unwrap();
+        let read = Checkpoint::from_file(dir.join("first"), config, hash).unwrap();
+        read.write_to_file(dir.join("second"), hash).unwrap();
+        assert_eq!(fs::read(dir.join("first")).unwrap(), fs::read(dir.join("second")).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        read
+    }
+
+    // The error of reading `checkpoint`, written for `written`, for `config`.
+    fn resume_error(name: &str, checkpoint: &Checkpoint, written: &Config, config: &Config) -> String {
+        let path = std::env::temp_dir().join(format!("keygen-checkpoint-{}-{}", name, std::process::id()));
+        checkpoint.write_to_file(&path, config_hash(written, &corpus())).unwrap();
+        let result = Checkpoint::from_file(&path, config, config_hash(config, &corpus()));
+        fs::remove_file(&path).unwrap();
+        match result {
+            Ok(_) => panic!("checkpoint was resumed"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn run_round_trip() {
+        let config = config(&["run", "corpus"]);
+        let mut state = RunState::new(&crate::layout::QWERTY_LAYOUT, 2.5);
+        state.iteration = 300;
+        state.draws = 1234;
+        state.best_layout = crate::layout::COLEMAK_LAYOUT.clone();
+        state.best_penalty = 1.25;
+        state.trajectory = trajectory(300);
+        let checkpoint = Checkpoint::Run {
+            completed: vec![(penalty::INIT_LAYOUT.clone(), trajectory(15))],
+            current: Some(state),
+        };
+        match round_trip("run", &checkpoint, &config) {
+            Checkpoint::Run { completed, current: Some(current) } => {
+                assert_eq!(completed.len(), 1);
+                assert_eq!(&completed[0].0, penalty::INIT_LAYOUT);
+                assert_eq!(completed[0].1.steps.len(), 15);
+                assert_eq!((current.iteration, current.draws), (300, 1234));
+                assert_eq!(current.layout, crate::layout::QWERTY_LAYOUT);
+                assert_eq!(current.best_layout, crate::layout::COLEMAK_LAYOUT);
+                assert_eq!((current.penalty, current.best_penalty), (2.5, 1.25));
+                assert_eq!(current.trajectory.steps[9].penalty, 0.1);
+            }
+            _ => panic!("not a run checkpoint"),
+        }
+    }
+
+    #[test]
+    fn refine_round_trip() {
+        let config = config(&["refine", "corpus"]);
+        let checkpoint = Checkpoint::Refine {
+            sweep: 3,
+            layout: crate::layout::QWERTY_LAYOUT.clone(),
+            sweeps: vec![3.0, 2.5, 2.25],
+        };
+        match round_trip("refine", &checkpoint, &config) {
+            Checkpoint::Refine { sweep, layout, sweeps } => {
+                assert_eq!(sweep, 3);
+                assert_eq!(layout, crate::layout::QWERTY_LAYOUT);
+                assert_eq!(sweeps, vec![3.0, 2.5, 2.25]);
+            }
+            _ => panic!("not a refine checkpoint"),
+        }
+    }
+
+    #[test]
+    fn configuration_must_match() {
+        let checkpoint = Checkpoint::Run { completed: Vec::new(), current: None };
+        let written = config(&["run", "corpus"]);
+        for args in [&["run", "corpus", "-s", "3"][..], &["run", "corpus", "--context", "5"], &["run", "other"]] {
+            let error = resume_error("config", &checkpoint, &written, &config(args));
+            assert!(error.contains("different configuration"), "{:?}: {}", args, error);
+        }
+        // Checkpoints are independent of how often they are written.
+        let every = config(&["run", "corpus", "--checkpoint-every", "10"]);
+        assert_eq!(config_hash(&every, &corpus()), config_hash(&written, &corpus()));
+    }
+
+    #[test]
+    fn command_must_match() {
+        let checkpoint = Checkpoint::Run { completed: Vec::new(), current: None };
+        let run = config(&["run", "corpus"]);
+        let mut refine = config(&["refine", "corpus"]);
+        // Only the command differs.
+        refine.command = Command::Run;
+        let hash = config_hash(&refine, &corpus());
+        refine.command = Command::Refine;
+        let path = std::env::temp_dir().join(format!("keygen-checkpoint-command-{}", std::process::id()));
+        checkpoint.write_to_file(&path, hash).unwrap();
+        let error = match Checkpoint::from_file(&path, &refine, hash) {
+            Ok(_) => panic!("run checkpoint was resumed by refine"),
+            Err(e) => e.to_string(),
+        };
+        fs::remove_file(&path).unwrap();
+        assert!(error.contains("belongs to the 'run' command"), "{}", error);
+        assert_ne!(config_hash(&run, &corpus()), config_hash(&refine, &corpus()));
+    }
+
+    #[test]
+    fn resumed_generator_continues() {
+        let draw = |rng: &mut RunRng| (rng.gen_range(0, 7usize), rng.next_f64(), rng.gen::<u32>());
+        let mut rng = RunRng::new(0);
+        let first: Vec<_> = (0..50).map(|_| draw(&mut rng)).collect();
+        let mut resumed = RunRng::new(rng.draws);
+        let second: Vec<_> = (0..50).map(|_| draw(&mut rng)).collect();
+        assert_eq!((0..50).map(|_| draw(&mut resumed)).collect::<Vec<_>>(), second);
+        assert_ne!(first, second);
+        assert_eq!(resumed.draws, rng.draws);
+    }
+}
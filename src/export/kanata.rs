@@ -0,0 +1,110 @@
+/// kanata configuration remapping the letter block of a QWERTY keyboard, with
+/// `fork` aliases for non-standard shift pairs.
+use std::fmt::Write as _;
+
+use super::{ansi_rows, has_custom_shift, is_placeholder, us_key};
+use crate::layout::Layout;
+
+pub fn render(layout: &Layout) -> String {
+    let lower = layout.lower();
+    let upper = layout.upper();
+    let (rows, misfits) = ansi_rows(layout);
+    let mut out = String::new();
+
+    out.push_str(";; Generated by keygen.\n");
+    out.push_str("(defcfg\n  process-unmapped-keys yes\n)\n\n");
+
+    out.push_str("(defsrc\n");
+    for row in &rows {
+        let keys: Vec<String> = row.iter().map(|(_, key)| name(*key)).collect();
+        let _ = writeln!(out, "  {}", keys.join(" "));
+    }
+    out.push_str(")\n\n");
+
+    let overrides: Vec<usize> = rows
+        .iter()
+        .flatten()
+        .map(|(pos, _)| *pos)
+        .filter(|&pos| !is_placeholder(lower[pos]) && has_custom_shift(layout, pos))
+        .collect();
+    if !overrides.is_empty() {
+        out.push_str(";; Shifted keys that differ from US ANSI.\n(defalias\n");
+        for (n, &pos) in overrides.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "  s{} (fork {} {} (lsft rsft)) ;; {:?} -> {:?}",
+                n,
+                action(lower[pos]),
+                shifted_action(upper[pos]),
+                lower[pos],
+                upper[pos]
+            );
+        }
+        out.push_str(")\n\n");
+    }
+
+    out.push_str("(deflayer base\n");
+    for row in &rows {
+        let keys: Vec<String> = row
+            .iter()
+            .map(|(pos, _)| match overrides.iter().position(|p| p == pos) {
+                Some(n) => format!("@s{}", n),
+                None => action(lower[*pos]),
+            })
+            .collect();
+        let _ = writeln!(out, "  {}", keys.join(" "));
+    }
+    out.push_str(")\n");
+
+    if !misfits.is_empty() {
+        out.push_str("\n;; Keys without a place on a row-staggered board:\n");
+        for pos in misfits {
+            let _ = writeln!(out, ";;   position {}: {:?} {:?}", pos, lower[pos], upper[pos]);
+        }
+    }
+    out
+}
+
+// Action typing `c` while shift is not held.
+fn action(c: char) -> String {
+    match us_key(c) {
+        Some((key, false)) => name(key),
+        Some((key, true)) => format!("S-{}", name(key)),
+        None => "XX".to_string(),
+    }
+}
+
+// Action typing `c` while shift is held.
+fn shifted_action(c: char) -> String {
+    match us_key(c) {
+        Some((key, true)) => name(key),
+        Some((key, false)) => format!("(unshift {})", name(key)),
+        None => "XX".to_string(),
+    }
+}
+
+fn name(key: char) -> String {
+    match key {
+        ' ' => "spc".to_string(),
+        '\n' => "ret".to_string(),
+        '\t' => "tab".to_string(),
+        '`' => "grv".to_string(),
+        _ => key.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{custom_shift_layout, misfits_layout};
+
+    #[test]
+    fn custom_shift() {
+        assert_eq!(render(&custom_shift_layout()), include_str!("../../tests/golden/custom-shift.kanata"));
+    }
+
+    #[test]
+    fn misfits() {
+        assert_eq!(render(&misfits_layout()), include_str!("../../tests/golden/misfits.kanata"));
+    }
+}
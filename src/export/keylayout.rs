@@ -0,0 +1,120 @@
+/// macOS `.keylayout` XML for the ANSI keyboard, to be installed into
+/// `~/Library/Keyboard Layouts`.
+use std::fmt::Write as _;
+
+use super::us_board;
+use crate::layout::Layout;
+
+// Virtual key codes of the keys in `US_KEYS`.
+const KEY_CODES: [u8; 48] = [
+    50, 18, 19, 20, 21, 23, 22, 26, 28, 25, 29, 27, 24, //
+    12, 13, 14, 15, 17, 16, 32, 34, 31, 35, 33, 30, 42, //
+    0, 1, 2, 3, 5, 4, 38, 40, 37, 41, 39, //
+    6, 7, 8, 9, 11, 45, 46, 43, 47, 44, //
+    49,
+];
+
+// Keys outside of `US_KEYS` that type control characters.
+const CONTROL_KEYS: [(u8, char); 4] = [(36, '\r'), (48, '\t'), (51, '\u{8}'), (53, '\u{1b}')];
+
+pub fn render(layout: &Layout) -> String {
+    let (keys, misfits) = us_board(layout);
+    let mut out = String::new();
+
+    out.push_str("<?xml version=\"1.1\" encoding=\"UTF-8\"?>\n");
+    out.push_str(
+        "<!DOCTYPE keyboard SYSTEM \"file://localhost/System/Library/DTDs/KeyboardLayout.dtd\">\n",
+    );
+    out.push_str("<!-- Generated by keygen. -->\n");
+    out.push_str("<keyboard group=\"126\" id=\"-28417\" name=\"keygen\" maxout=\"1\">\n");
+    out.push_str("    <layouts>\n");
+    out.push_str(
+        "        <layout first=\"0\" last=\"17\" mapSet=\"ANSI\" modifiers=\"Modifiers\"/>\n",
+    );
+    out.push_str("    </layouts>\n");
+    out.push_str("    <modifierMap id=\"Modifiers\" defaultIndex=\"0\">\n");
+    out.push_str("        <keyMapSelect mapIndex=\"0\">\n            <modifier keys=\"\"/>\n        </keyMapSelect>\n");
+    out.push_str("        <keyMapSelect mapIndex=\"1\">\n            <modifier keys=\"anyShift caps?\"/>\n        </keyMapSelect>\n");
+    out.push_str("        <keyMapSelect mapIndex=\"2\">\n            <modifier keys=\"caps\"/>\n        </keyMapSelect>\n");
+    out.push_str("    </modifierMap>\n");
+    out.push_str("    <keyMapSet id=\"ANSI\">\n");
+
+    // Caps lock only affects letters that have an upper case on the same key.
+    let caps = |lower: Option<char>, upper: Option<char>| match (lower, upper) {
+        (Some(l), Some(u)) if l.is_lowercase() && l.to_uppercase().eq(std::iter::once(u)) => upper,
+        _ => lower,
+    };
+    type Map<'a> = &'a dyn Fn(Option<char>, Option<char>) -> Option<char>;
+    let maps: [Map; 3] = [&|lower, _| lower, &|_, upper| upper, &caps];
+    for (index, map) in maps.iter().enumerate() {
+        let _ = writeln!(out, "        <keyMap index=\"{}\">", index);
+        for (&code, &(_, lower, upper)) in KEY_CODES.iter().zip(keys.iter()) {
+            let _ = writeln!(
+                out,
+                "            <key code=\"{}\" output=\"{}\"/>",
+                code,
+                escape(map(lower, upper))
+            );
+        }
+        for &(code, c) in CONTROL_KEYS.iter() {
+            let _ = writeln!(
+                out,
+                "            <key code=\"{}\" output=\"{}\"/>",
+                code,
+                escape(Some(c))
+            );
+        }
+        out.push_str("        </keyMap>\n");
+    }
+    out.push_str("    </keyMapSet>\n");
+
+    if !misfits.is_empty() {
+        out.push_str("    <!-- Keys without a place on a row-staggered board:\n");
+        for pos in misfits {
+            let _ = writeln!(
+                out,
+                "         position {}: {:?} {:?}",
+                pos,
+                layout.lower()[pos],
+                layout.upper()[pos]
+            );
+        }
+        out.push_str("    -->\n");
+    }
+    out.push_str("</keyboard>\n");
+    out
+}
+
+fn escape(c: Option<char>) -> String {
+    let c = match c {
+        Some(c) => c,
+        None => return String::new(),
+    };
+    match c {
+        '&' => "&amp;".to_string(),
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        '"' => "&quot;".to_string(),
+        '\'' => "&apos;".to_string(),
+        _ if c.is_control() || c == ' ' => format!("&#x{:04X};", c as u32),
+        _ => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::custom_shift_layout;
+    use crate::layout::QWERTY_LAYOUT;
+
+    #[test]
+    fn qwerty() {
+        assert_eq!(render(&QWERTY_LAYOUT), include_str!("../../tests/golden/qwerty.keylayout"));
+    }
+
+    #[test]
+    fn custom_shift() {
+        let golden = include_str!("../../tests/golden/custom-shift.keylayout");
+        assert_eq!(render(&custom_shift_layout()), golden);
+    }
+}
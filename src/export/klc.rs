@@ -0,0 +1,176 @@
+/// Microsoft Keyboard Layout Creator source file. MSKLC expects UTF-16 with a
+/// byte order mark and CRLF line endings.
+use std::fmt::Write as _;
+
+use super::{us_board, us_key};
+use crate::layout::Layout;
+
+// Scan codes of the keys in `US_KEYS`.
+const SCAN_CODES: [u8; 48] = [
+    0x29, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, //
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x2b, //
+    0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, //
+    0x2c, 0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, //
+    0x39,
+];
+
+pub fn render(layout: &Layout) -> Vec<u8> {
+    let (keys, misfits) = us_board(layout);
+    let mut out = String::new();
+
+    out.push_str("KBD\tkeygen\t\"keygen\"\n\n");
+    out.push_str("COPYRIGHT\t\"Generated by keygen\"\n\n");
+    out.push_str("COMPANY\t\"keygen\"\n\n");
+    out.push_str("LOCALENAME\t\"en-US\"\n\n");
+    out.push_str("LOCALEID\t\"00000409\"\n\n");
+    out.push_str("VERSION\t1.0\n\n");
+    out.push_str("SHIFTSTATE\n\n0\t//Column 4\n1\t//Column 5 : Shft\n\n");
+    out.push_str("LAYOUT\t\t;an extra '@' at the end is a dead key\n\n");
+    out.push_str("//SC\tVK_\t\tCap\t0\t1\n//--\t----\t\t----\t----\t----\n\n");
+
+    // Shortcuts follow the virtual key, so it belongs to the character rather
+    // than to the physical key. Windows needs every virtual key to be unique,
+    // so keys with a character that is typed elsewhere as well get one of the
+    // virtual keys left over.
+    let mut virtual_keys: Vec<Option<String>> = keys
+        .iter()
+        .map(|(key, lower, _)| lower.and_then(virtual_key).or_else(|| virtual_key(*key)))
+        .collect();
+    let mut spare = keys
+        .iter()
+        .filter_map(|(key, _, _)| virtual_key(*key))
+        .filter(|vk| !virtual_keys.contains(&Some(vk.clone())))
+        .collect::<Vec<_>>()
+        .into_iter();
+    for i in 0..virtual_keys.len() {
+        if virtual_keys[..i].contains(&virtual_keys[i]) {
+            virtual_keys[i] = spare.next();
+        }
+    }
+
+    for ((&scan_code, &(_, lower, upper)), vk) in
+        SCAN_CODES.iter().zip(keys.iter()).zip(virtual_keys)
+    {
+        let caps = match (lower, upper) {
+            (Some(lower), Some(upper)) => {
+                lower.is_lowercase() && lower.to_uppercase().eq(std::iter::once(upper))
+            }
+            _ => false,
+        };
+        let _ = writeln!(
+            out,
+            "{:02x}\t{}\t\t{}\t{}\t{}\t// {}, {}",
+            scan_code,
+            vk.unwrap_or_else(|| "-1".to_string()),
+            caps as u8,
+            character(lower),
+            character(upper),
+            describe(lower),
+            describe(upper)
+        );
+    }
+
+    if !misfits.is_empty() {
+        out.push_str("\n// Keys without a place on a row-staggered board:\n");
+        for pos in misfits {
+            let _ = writeln!(
+                out,
+                "//   position {}: {:?} {:?}",
+                pos,
+                layout.lower()[pos],
+                layout.upper()[pos]
+            );
+        }
+    }
+
+    out.push_str("\n\nKEYNAME\n\n01\tEsc\n0e\tBackspace\n0f\tTab\n1c\tEnter\n1d\tCtrl\n");
+    out.push_str("2a\tShift\n36\t\"Right Shift\"\n38\tAlt\n39\tSpace\n\n");
+    out.push_str("DESCRIPTIONS\n\n0409\tkeygen\n\n");
+    out.push_str("LANGUAGENAMES\n\n0409\tEnglish (United States)\n\n");
+    out.push_str("ENDKBD\n");
+
+    let mut bytes = vec![0xff, 0xfe];
+    for unit in out.replace('\n', "\r\n").encode_utf16() {
+        bytes.extend_from_slice(&unit.to_le_bytes());
+    }
+    bytes
+}
+
+fn virtual_key(c: char) -> Option<String> {
+    let (key, _) = us_key(c)?;
+    if key.is_ascii_alphanumeric() {
+        return Some(key.to_ascii_uppercase().to_string());
+    }
+    let name = match key {
+        ' ' => "SPACE",
+        '`' => "OEM_3",
+        '-' => "OEM_MINUS",
+        '=' => "OEM_PLUS",
+        '[' => "OEM_4",
+        ']' => "OEM_6",
+        '\\' => "OEM_5",
+        ';' => "OEM_1",
+        '\'' => "OEM_7",
+        ',' => "OEM_COMMA",
+        '.' => "OEM_PERIOD",
+        '/' => "OEM_2",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+// A character as written in the shift state columns; `-1` for none.
+fn character(c: Option<char>) -> String {
+    match c {
+        Some(c) if c as u32 > 0xffff => "-1".to_string(),
+        Some(c) if c.is_ascii_alphanumeric() => c.to_string(),
+        Some(c) => format!("{:04x}", c as u32),
+        None => "-1".to_string(),
+    }
+}
+
+fn describe(c: Option<char>) -> String {
+    match c {
+        Some(c) => format!("{:?}", c),
+        None => "none".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::custom_shift_layout;
+    use crate::layout::QWERTY_LAYOUT;
+
+    #[test]
+    fn qwerty() {
+        assert!(render(&QWERTY_LAYOUT) == include_bytes!("../../tests/golden/qwerty.klc").to_vec());
+    }
+
+    #[test]
+    fn custom_shift() {
+        let golden = include_bytes!("../../tests/golden/custom-shift.klc").to_vec();
+        assert!(render(&custom_shift_layout()) == golden);
+    }
+
+    #[test]
+    fn utf16_with_bom_and_crlf() {
+        let bytes = render(&QWERTY_LAYOUT);
+        // BOM followed by "KBD\t" in UTF-16LE.
+        assert_eq!(bytes[..10], [0xff, 0xfe, b'K', 0, b'B', 0, b'D', 0, b'\t', 0]);
+        assert_eq!(bytes.len() % 2, 0);
+        let units: Vec<u16> = bytes[2..]
+            .chunks(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+        for (i, &unit) in units.iter().enumerate() {
+            if unit == u16::from(b'\n') {
+                assert!(i > 0 && units[i - 1] == u16::from(b'\r'), "bare LF at code unit {}", i);
+            }
+            if unit == u16::from(b'\r') {
+                assert_eq!(units.get(i + 1), Some(&u16::from(b'\n')), "bare CR at code unit {}", i);
+            }
+        }
+        assert!(bytes.ends_with(&[b'K', 0, b'B', 0, b'D', 0, b'\r', 0, b'\n', 0]));
+    }
+}
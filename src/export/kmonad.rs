@@ -0,0 +1,137 @@
+/// KMonad configuration remapping the letter block of a QWERTY keyboard. If
+/// the layout has non-standard shift pairs, each shift key still sends shift
+/// but also switches to a layer that types the upper layer explicitly.
+use std::fmt::Write as _;
+
+use super::{ansi_rows, has_custom_shift, is_placeholder, us_key};
+use crate::layout::Layout;
+
+// Shift keys with the alias and layer of each.
+const SHIFTS: [(&str, &str, &str); 2] = [("lsft", "lsh", "left-shift"), ("rsft", "rsh", "right-shift")];
+
+pub fn render(layout: &Layout) -> String {
+    let lower = layout.lower();
+    let upper = layout.upper();
+    let (rows, misfits) = ansi_rows(layout);
+    let custom_shift = rows
+        .iter()
+        .flatten()
+        .any(|(pos, _)| !is_placeholder(lower[*pos]) && has_custom_shift(layout, *pos));
+    let mut out = String::new();
+
+    out.push_str(";; Generated by keygen. Point `input` at your keyboard's device.\n");
+    out.push_str("(defcfg\n");
+    out.push_str("  input  (device-file \"/dev/input/by-path/platform-i8042-serio-0-event-kbd\")\n");
+    out.push_str("  output (uinput-sink \"keygen\")\n");
+    out.push_str("  fallthrough true\n)\n\n");
+
+    out.push_str("(defsrc\n");
+    for row in &rows {
+        let keys: Vec<String> = row.iter().map(|(_, key)| name(*key)).collect();
+        let _ = writeln!(out, "  {}", keys.join(" "));
+    }
+    if custom_shift {
+        out.push_str("  lsft rsft\n");
+    }
+    out.push_str(")\n\n");
+
+    if custom_shift {
+        out.push_str(";; Shift keys send shift as usual and switch to the layer with the upper\n");
+        out.push_str(";; layer of the layout while held.\n(defalias\n");
+        for (key, alias, layer) in &SHIFTS {
+            let _ = writeln!(out, "  {} (around {} (layer-toggle {}))", alias, key, layer);
+        }
+        out.push_str(")\n\n");
+    }
+
+    out.push_str("(deflayer base\n");
+    for row in &rows {
+        let keys: Vec<String> = row.iter().map(|(pos, _)| button(lower[*pos])).collect();
+        let _ = writeln!(out, "  {}", keys.join(" "));
+    }
+    if custom_shift {
+        out.push_str("  @lsh @rsh\n");
+    }
+    out.push_str(")\n");
+
+    if custom_shift {
+        for (key, _, layer) in &SHIFTS {
+            let _ = write!(out, "\n(deflayer {}\n", layer);
+            for row in &rows {
+                let keys: Vec<String> = row
+                    .iter()
+                    .map(|(pos, _)| {
+                        // Other keys fall through to the base layer, where
+                        // shift types their standard upper character.
+                        if !is_placeholder(lower[*pos]) && has_custom_shift(layout, *pos) {
+                            shifted_button(upper[*pos], key)
+                        } else {
+                            "_".to_string()
+                        }
+                    })
+                    .collect();
+                let _ = writeln!(out, "  {}", keys.join(" "));
+            }
+            out.push_str("  _ _\n)\n");
+        }
+    }
+
+    if !misfits.is_empty() {
+        out.push_str("\n;; Keys without a place on a row-staggered board:\n");
+        for pos in misfits {
+            let _ = writeln!(out, ";;   position {}: {:?} {:?}", pos, lower[pos], upper[pos]);
+        }
+    }
+    out
+}
+
+// Button typing `c` while shift is not held.
+fn button(c: char) -> String {
+    match us_key(c) {
+        Some((key, false)) => name(key),
+        Some((key, true)) => format!("S-{}", name(key)),
+        None => "XX".to_string(),
+    }
+}
+
+// Button typing `c` while `shift` is held. Characters without shift release it
+// for the keystroke.
+fn shifted_button(c: char, shift: &str) -> String {
+    match us_key(c) {
+        Some((key, true)) => name(key),
+        Some((key, false)) => format!(
+            "(tap-macro (release-only {}) {} (press-only {}))",
+            shift,
+            name(key),
+            shift
+        ),
+        None => "XX".to_string(),
+    }
+}
+
+fn name(key: char) -> String {
+    match key {
+        ' ' => "spc".to_string(),
+        '\n' => "ret".to_string(),
+        '\t' => "tab".to_string(),
+        '`' => "grv".to_string(),
+        '\\' => "\\\\".to_string(),
+        _ => key.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{custom_shift_layout, misfits_layout};
+
+    #[test]
+    fn custom_shift() {
+        assert_eq!(render(&custom_shift_layout()), include_str!("../../tests/golden/custom-shift.kmonad"));
+    }
+
+    #[test]
+    fn misfits() {
+        assert_eq!(render(&misfits_layout()), include_str!("../../tests/golden/misfits.kmonad"));
+    }
+}
@@ -0,0 +1,223 @@
+/// Exports layouts to configuration files of keyboard firmwares and remappers.
+mod kanata;
+mod keylayout;
+mod klc;
+mod kmonad;
+mod qmk;
+pub mod xkb;
+mod zmk;
+
+use std::io::Write;
+
+use crate::app::Config;
+use crate::layout::{us_shifted, Layout, US_SHIFT_PAIRS};
+use crate::layout_file;
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Keygen,
+    Qmk,
+    Zmk,
+    Xkb,
+    Kanata,
+    Kmonad,
+    Klc,
+    Keylayout,
+}
+
+impl Format {
+    pub fn from_name(s: &str) -> Option<Format> {
+        match s {
+            "keygen" => Some(Format::Keygen),
+            "qmk" => Some(Format::Qmk),
+            "zmk" => Some(Format::Zmk),
+            "xkb" => Some(Format::Xkb),
+            "kanata" => Some(Format::Kanata),
+            "kmonad" => Some(Format::Kmonad),
+            "klc" => Some(Format::Klc),
+            "keylayout" => Some(Format::Keylayout),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExportOptions {
+    pub format: Format,
+    // Name of the keyboard's layout macro in QMK.
+    pub board_macro: String,
+    // Keygen position of every physical key of the board, in the order the
+    // firmware expects them. `None` marks keys that keygen doesn't know about.
+    pub key_order: Option<Vec<Option<usize>>>,
+}
+
+impl ExportOptions {
+    /// Parses a comma separated list of keygen positions, with `-` for board
+    /// keys that are not part of the keygen layout.
+    pub fn parse_key_order(s: &str) -> Result<Vec<Option<usize>>> {
+        s.split(',')
+            .map(|key| match key.trim() {
+                "-" => Ok(None),
+                key => match key.parse::<usize>() {
+                    Ok(pos) if pos < 36 => Ok(Some(pos)),
+                    _ => Err(format!("Invalid position '{}' in '--key-order'", key).into()),
+                },
+            })
+            .collect()
+    }
+
+    // Board keys grouped into the lines they are printed on. Without an
+    // explicit key order these are the rows of the keygen layout.
+    fn rows(&self) -> Vec<Vec<Option<usize>>> {
+        match &self.key_order {
+            Some(order) => order.chunks(12).map(|row| row.to_vec()).collect(),
+            None => {
+                let mut pos = 0;
+                [11, 11, 10, 2, 2]
+                    .iter()
+                    .map(|len| {
+                        pos += len;
+                        (pos - len..pos).map(Some).collect()
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+pub fn run(config: &Config, options: &ExportOptions) -> Result<()> {
+    let output = match options.format {
+        Format::Keygen => layout_file::format(&config.layout, &config.layout_info).into_bytes(),
+        Format::Qmk => qmk::render(&config.layout, options).into_bytes(),
+        Format::Zmk => zmk::render(&config.layout, options).into_bytes(),
+        Format::Xkb => xkb::render(&config.layout).into_bytes(),
+        Format::Kanata => kanata::render(&config.layout).into_bytes(),
+        Format::Kmonad => kmonad::render(&config.layout).into_bytes(),
+        Format::Klc => klc::render(&config.layout),
+        Format::Keylayout => keylayout::render(&config.layout).into_bytes(),
+    };
+    match &config.output {
+        Some(path) => {
+            std::fs::write(path, output)?;
+            eprintln!("Layout written to {}", path.display());
+        }
+        None => std::io::stdout().write_all(&output)?,
+    }
+    Ok(())
+}
+
+// Keys without a character are represented by `*` or `\0` in layouts.
+fn is_placeholder(c: char) -> bool {
+    c == '*' || c == '\0'
+}
+
+// Row and column of the ANSI key that keygen position `pos` goes to. Rows 0 to
+// 2 are the top, home and bottom letter rows counted from their left-most
+// letter key, row 3 is the space bar. The thumb key holding space maps to the
+// space bar; the other thumb keys have no place on a row-staggered board.
+fn ansi_key(layout: &Layout, pos: usize) -> Option<(usize, usize)> {
+    match pos {
+        0..=10 => Some((0, pos)),
+        11..=21 => Some((1, pos - 11)),
+        22..=31 => Some((2, pos - 22)),
+        _ if layout.lower()[pos] == ' ' => Some((3, 0)),
+        _ => None,
+    }
+}
+
+// Unshifted characters of the ANSI keys returned by `ansi_key`, i.e. QWERTY.
+const ANSI_ROWS: [&str; 4] = ["qwertyuiop[", "asdfghjkl;'", "zxcvbnm,./", " "];
+
+// Keygen positions with a character grouped by the ANSI row they go to, each
+// with the QWERTY character of its key, followed by the positions that don't
+// fit on the board.
+fn ansi_rows(layout: &Layout) -> (Vec<Vec<(usize, char)>>, Vec<usize>) {
+    let mut rows = vec![Vec::new(); ANSI_ROWS.len()];
+    let mut misfits = Vec::new();
+    for pos in 0..36 {
+        if is_placeholder(layout.lower()[pos]) && is_placeholder(layout.upper()[pos]) {
+            continue;
+        }
+        match ansi_key(layout, pos) {
+            // A second thumb key with space has no space bar left.
+            Some((3, _)) if !rows[3].is_empty() => misfits.push(pos),
+            Some((row, col)) => rows[row].push((pos, ANSI_ROWS[row].as_bytes()[col] as char)),
+            None => misfits.push(pos),
+        }
+    }
+    rows.retain(|row| !row.is_empty());
+    (rows, misfits)
+}
+
+// Unshifted characters of the character keys of a US ANSI keyboard.
+const US_KEYS: &str = "`1234567890-=qwertyuiop[]\\asdfghjkl;'zxcvbnm,./ ";
+
+// Lower and upper character of every key in `US_KEYS` once `layout` is placed
+// on the letter block, followed by the positions that don't fit on the board.
+// Keys without a character are `None`, as `*` is a real character on US keys.
+type Board = Vec<(char, Option<char>, Option<char>)>;
+
+fn us_board(layout: &Layout) -> (Board, Vec<usize>) {
+    let character = |c: char| if is_placeholder(c) { None } else { Some(c) };
+    let (rows, misfits) = ansi_rows(layout);
+    let keys = US_KEYS
+        .chars()
+        .map(|key| {
+            let pos = rows.iter().flatten().find(|(_, k)| *k == key).map(|(pos, _)| *pos);
+            match pos {
+                Some(pos) => (key, character(layout.lower()[pos]), character(layout.upper()[pos])),
+                None => (key, Some(key), Some(us_shifted(key).unwrap_or(key))),
+            }
+        })
+        .collect();
+    (keys, misfits)
+}
+
+// Unshifted character of the US ANSI key that types `c`, and whether it
+// needs shift.
+fn us_key(c: char) -> Option<(char, bool)> {
+    if c.is_ascii_uppercase() {
+        return Some((c.to_ascii_lowercase(), true));
+    }
+    if let Some((lower, _)) = US_SHIFT_PAIRS.iter().find(|(_, upper)| *upper == c) {
+        return Some((*lower, true));
+    }
+    if c.is_ascii_lowercase()
+        || c.is_ascii_digit()
+        || c == ' '
+        || c == '\n'
+        || c == '\t'
+        || US_SHIFT_PAIRS.iter().any(|(lower, _)| *lower == c)
+    {
+        Some((c, false))
+    } else {
+        None
+    }
+}
+
+// Whether the upper layer of `layout` deviates from the US shift pairs at `pos`.
+fn has_custom_shift(layout: &Layout, pos: usize) -> bool {
+    let lower = layout.lower()[pos];
+    let upper = layout.upper()[pos];
+    !is_placeholder(upper) && upper != lower && us_shifted(lower) != Some(upper)
+}
+
+// Layout whose upper layer deviates from the US shift pairs, for golden tests.
+#[cfg(test)]
+fn custom_shift_layout() -> Layout {
+    layout_file::parse(include_str!("../../tests/golden/custom-shift.layout")).unwrap().0
+}
+
+// Layout with keys on the thumbs that don't fit a row-staggered board, one of
+// them with a custom shift, for golden tests.
+#[cfg(test)]
+fn misfits_layout() -> Layout {
+    layout_file::parse(include_str!("../../tests/golden/misfits.layout")).unwrap().0
+}
+
+// Options of an export without a key order, for tests.
+#[cfg(test)]
+fn test_options(format: Format) -> ExportOptions {
+    ExportOptions { format, board_macro: "LAYOUT".to_string(), key_order: None }
+}
@@ -0,0 +1,163 @@
+/// QMK `keymap.c` source with key overrides for non-standard shift pairs.
+use std::fmt::Write as _;
+
+use super::{has_custom_shift, is_placeholder, ExportOptions};
+use crate::layout::Layout;
+
+pub fn render(layout: &Layout, options: &ExportOptions) -> String {
+    let lower = layout.lower();
+    let upper = layout.upper();
+    let mut out = String::new();
+
+    out.push_str("// Generated by keygen.\n#include QMK_KEYBOARD_H\n\n");
+    out.push_str("enum layers {\n    BASE,\n};\n\n");
+
+    let overrides: Vec<usize> = (0..36)
+        .filter(|&pos| !is_placeholder(lower[pos]) && has_custom_shift(layout, pos))
+        .collect();
+    if !overrides.is_empty() {
+        out.push_str("// Shifted keys that differ from US ANSI. Requires KEY_OVERRIDE_ENABLE = yes\n");
+        out.push_str("// in rules.mk.\n");
+        for (n, &pos) in overrides.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "const key_override_t shift_override_{} = ko_make_basic(MOD_MASK_SHIFT, {}, {}); // {:?} -> {:?}",
+                n,
+                keycode(lower[pos]),
+                keycode(upper[pos]),
+                lower[pos],
+                upper[pos]
+            );
+        }
+        out.push_str("\nconst key_override_t *key_overrides[] = {\n");
+        for n in 0..overrides.len() {
+            let _ = writeln!(out, "    &shift_override_{},", n);
+        }
+        out.push_str("};\n\n");
+    }
+
+    let keys: Vec<Vec<String>> = options
+        .rows()
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|pos| match pos {
+                    Some(pos) => keycode(lower[*pos]),
+                    None => "KC_NO".to_string(),
+                })
+                .collect()
+        })
+        .collect();
+    let width = keys.iter().flatten().map(|k| k.len()).max().unwrap_or(0) + 2;
+
+    out.push_str("const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {\n");
+    let _ = writeln!(out, "    [BASE] = {}(", options.board_macro);
+    let last_row = keys.len().saturating_sub(1);
+    for (r, row) in keys.iter().enumerate() {
+        let last_key = row.len().saturating_sub(1);
+        let line = row
+            .iter()
+            .enumerate()
+            .map(|(k, key)| {
+                if r == last_row && k == last_key {
+                    key.clone()
+                } else {
+                    format!("{:<w$}", format!("{},", key), w = width)
+                }
+            })
+            .collect::<String>();
+        let _ = writeln!(out, "        {}", line.trim_end());
+    }
+    out.push_str("    ),\n};\n");
+
+    let unknown: Vec<char> = lower
+        .iter()
+        .chain(upper.iter())
+        .filter(|c| !is_placeholder(**c) && code(**c).is_none())
+        .cloned()
+        .collect();
+    if !unknown.is_empty() {
+        let _ = writeln!(
+            out,
+            "\n// No keycode for: {}",
+            unknown.iter().map(|c| format!("{:?}", c)).collect::<Vec<_>>().join(" ")
+        );
+    }
+    out
+}
+
+fn keycode(c: char) -> String {
+    if is_placeholder(c) {
+        return "KC_NO".to_string();
+    }
+    match code(c) {
+        Some(code) => code,
+        None => "KC_NO".to_string(),
+    }
+}
+
+fn code(c: char) -> Option<String> {
+    if c.is_ascii_lowercase() || c.is_ascii_digit() {
+        return Some(format!("KC_{}", c.to_ascii_uppercase()));
+    }
+    if c.is_ascii_uppercase() {
+        return Some(format!("S(KC_{})", c));
+    }
+    let code = match c {
+        ' ' => "KC_SPC",
+        '\n' => "KC_ENT",
+        '\t' => "KC_TAB",
+        '-' => "KC_MINS",
+        '=' => "KC_EQL",
+        '[' => "KC_LBRC",
+        ']' => "KC_RBRC",
+        '\\' => "KC_BSLS",
+        ';' => "KC_SCLN",
+        '\'' => "KC_QUOT",
+        '`' => "KC_GRV",
+        ',' => "KC_COMM",
+        '.' => "KC_DOT",
+        '/' => "KC_SLSH",
+        '~' => "KC_TILD",
+        '!' => "KC_EXLM",
+        '@' => "KC_AT",
+        '#' => "KC_HASH",
+        '$' => "KC_DLR",
+        '%' => "KC_PERC",
+        '^' => "KC_CIRC",
+        '&' => "KC_AMPR",
+        '*' => "KC_ASTR",
+        '(' => "KC_LPRN",
+        ')' => "KC_RPRN",
+        '_' => "KC_UNDS",
+        '+' => "KC_PLUS",
+        '{' => "KC_LCBR",
+        '}' => "KC_RCBR",
+        '|' => "KC_PIPE",
+        ':' => "KC_COLN",
+        '"' => "KC_DQUO",
+        '<' => "KC_LABK",
+        '>' => "KC_RABK",
+        '?' => "KC_QUES",
+        _ => return None,
+    };
+    Some(code.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{custom_shift_layout, misfits_layout, test_options, Format};
+
+    #[test]
+    fn custom_shift() {
+        let golden = include_str!("../../tests/golden/custom-shift.qmk");
+        assert_eq!(render(&custom_shift_layout(), &test_options(Format::Qmk)), golden);
+    }
+
+    #[test]
+    fn misfits() {
+        let golden = include_str!("../../tests/golden/misfits.qmk");
+        assert_eq!(render(&misfits_layout(), &test_options(Format::Qmk)), golden);
+    }
+}
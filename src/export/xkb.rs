@@ -0,0 +1,118 @@
+/// XKB `symbols` file for X11 and Wayland, placed on the letter block of an
+/// ANSI keyboard.
+use std::fmt::Write as _;
+
+use super::{ansi_key, is_placeholder};
+use crate::layout::Layout;
+
+const ROWS: [&str; 3] = ["AD", "AC", "AB"];
+
+pub fn render(layout: &Layout) -> String {
+    let lower = layout.lower();
+    let upper = layout.upper();
+    let mut out = String::new();
+
+    out.push_str("// Generated by keygen.\n");
+    out.push_str("// Copy to /usr/share/X11/xkb/symbols/keygen and enable with `setxkbmap keygen`.\n");
+    out.push_str("default partial alphanumeric_keys\nxkb_symbols \"basic\" {\n");
+    out.push_str("    include \"us(basic)\"\n    name[Group1] = \"keygen\";\n\n");
+
+    let mut misfits = Vec::new();
+    for pos in 0..36 {
+        if is_placeholder(lower[pos]) && is_placeholder(upper[pos]) {
+            continue;
+        }
+        let name = match ansi_key(layout, pos) {
+            Some((3, _)) => "SPCE".to_string(),
+            Some((row, col)) => format!("{}{:02}", ROWS[row], col + 1),
+            None => {
+                misfits.push(pos);
+                continue;
+            }
+        };
+        let levels = if is_placeholder(upper[pos]) {
+            keysym(lower[pos])
+        } else {
+            format!("{}, {}", keysym(lower[pos]), keysym(upper[pos]))
+        };
+        let _ = writeln!(out, "    key <{}> {{ [ {} ] }};", name, levels);
+    }
+
+    if !misfits.is_empty() {
+        out.push_str("\n    // Keys without a place on a row-staggered board:\n");
+        for pos in misfits {
+            let _ = writeln!(out, "    //   position {}: {:?} {:?}", pos, lower[pos], upper[pos]);
+        }
+    }
+    out.push_str("};\n");
+    out
+}
+
+fn keysym(c: char) -> String {
+    if is_placeholder(c) {
+        return "NoSymbol".to_string();
+    }
+    if c.is_ascii_alphanumeric() {
+        return c.to_string();
+    }
+    match KEYSYMS.iter().find(|(k, _)| *k == c) {
+        Some((_, name)) => name.to_string(),
+        // Any other character by its Unicode keysym.
+        None => format!("U{:04X}", c as u32),
+    }
+}
+
+/// Keysym names of the characters that aren't letters or digits.
+pub const KEYSYMS: [(char, &str); 35] = [
+    (' ', "space"),
+    ('\n', "Return"),
+    ('\t', "Tab"),
+    ('-', "minus"),
+    ('=', "equal"),
+    ('[', "bracketleft"),
+    (']', "bracketright"),
+    ('\\', "backslash"),
+    (';', "semicolon"),
+    ('\'', "apostrophe"),
+    ('`', "grave"),
+    (',', "comma"),
+    ('.', "period"),
+    ('/', "slash"),
+    ('~', "asciitilde"),
+    ('!', "exclam"),
+    ('@', "at"),
+    ('#', "numbersign"),
+    ('$', "dollar"),
+    ('%', "percent"),
+    ('^', "asciicircum"),
+    ('&', "ampersand"),
+    ('*', "asterisk"),
+    ('(', "parenleft"),
+    (')', "parenright"),
+    ('_', "underscore"),
+    ('+', "plus"),
+    ('{', "braceleft"),
+    ('}', "braceright"),
+    ('|', "bar"),
+    (':', "colon"),
+    ('"', "quotedbl"),
+    ('<', "less"),
+    ('>', "greater"),
+    ('?', "question"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{custom_shift_layout, misfits_layout};
+
+    #[test]
+    fn custom_shift() {
+        assert_eq!(render(&custom_shift_layout()), include_str!("../../tests/golden/custom-shift.xkb"));
+    }
+
+    #[test]
+    fn misfits() {
+        assert_eq!(render(&misfits_layout()), include_str!("../../tests/golden/misfits.xkb"));
+    }
+}
@@ -0,0 +1,146 @@
+/// ZMK devicetree `.keymap` with mod-morph behaviours for non-standard shift
+/// pairs.
+use std::fmt::Write as _;
+
+use super::{has_custom_shift, is_placeholder, ExportOptions};
+use crate::layout::Layout;
+
+pub fn render(layout: &Layout, options: &ExportOptions) -> String {
+    let lower = layout.lower();
+    let upper = layout.upper();
+    let mut out = String::new();
+
+    out.push_str("// Generated by keygen.\n");
+    out.push_str("#include <behaviors.dtsi>\n#include <dt-bindings/zmk/keys.h>\n\n/ {\n");
+
+    let overrides: Vec<usize> = (0..36)
+        .filter(|&pos| !is_placeholder(lower[pos]) && has_custom_shift(layout, pos))
+        .collect();
+    if !overrides.is_empty() {
+        out.push_str("    behaviors {\n");
+        for (n, &pos) in overrides.iter().enumerate() {
+            let _ = writeln!(out, "        // {:?} shifts to {:?}", lower[pos], upper[pos]);
+            let _ = writeln!(out, "        shift_{n}: shift_{n} {{", n = n);
+            out.push_str("            compatible = \"zmk,behavior-mod-morph\";\n");
+            out.push_str("            #binding-cells = <0>;\n");
+            let _ = writeln!(
+                out,
+                "            bindings = <{}>, <{}>;",
+                binding(lower[pos]),
+                binding(upper[pos])
+            );
+            out.push_str("            mods = <(MOD_LSFT|MOD_RSFT)>;\n");
+            out.push_str("        };\n");
+        }
+        out.push_str("    };\n\n");
+    }
+
+    let keys: Vec<Vec<String>> = options
+        .rows()
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|pos| match pos {
+                    Some(pos) => match overrides.iter().position(|p| p == pos) {
+                        Some(n) => format!("&shift_{}", n),
+                        None => binding(lower[*pos]),
+                    },
+                    None => "&none".to_string(),
+                })
+                .collect()
+        })
+        .collect();
+    let width = keys.iter().flatten().map(|k| k.len()).max().unwrap_or(0) + 1;
+
+    out.push_str("    keymap {\n        compatible = \"zmk,keymap\";\n\n");
+    out.push_str("        default_layer {\n            display-name = \"Base\";\n");
+    out.push_str("            bindings = <\n");
+    for row in &keys {
+        let line = row
+            .iter()
+            .map(|key| format!("{:<w$}", key, w = width))
+            .collect::<String>();
+        let _ = writeln!(out, "                {}", line.trim_end());
+    }
+    out.push_str("            >;\n        };\n    };\n};\n");
+    out
+}
+
+fn binding(c: char) -> String {
+    if is_placeholder(c) {
+        return "&none".to_string();
+    }
+    match code(c) {
+        Some(code) => format!("&kp {}", code),
+        None => "&none".to_string(),
+    }
+}
+
+fn code(c: char) -> Option<String> {
+    if c.is_ascii_lowercase() {
+        return Some(c.to_ascii_uppercase().to_string());
+    }
+    if c.is_ascii_uppercase() {
+        return Some(format!("LS({})", c));
+    }
+    if c.is_ascii_digit() {
+        return Some(format!("N{}", c));
+    }
+    let code = match c {
+        ' ' => "SPACE",
+        '\n' => "RET",
+        '\t' => "TAB",
+        '-' => "MINUS",
+        '=' => "EQUAL",
+        '[' => "LBKT",
+        ']' => "RBKT",
+        '\\' => "BSLH",
+        ';' => "SEMI",
+        '\'' => "SQT",
+        '`' => "GRAVE",
+        ',' => "COMMA",
+        '.' => "DOT",
+        '/' => "FSLH",
+        '~' => "TILDE",
+        '!' => "EXCL",
+        '@' => "AT",
+        '#' => "HASH",
+        '$' => "DLLR",
+        '%' => "PRCNT",
+        '^' => "CARET",
+        '&' => "AMPS",
+        '*' => "STAR",
+        '(' => "LPAR",
+        ')' => "RPAR",
+        '_' => "UNDER",
+        '+' => "PLUS",
+        '{' => "LBRC",
+        '}' => "RBRC",
+        '|' => "PIPE",
+        ':' => "COLON",
+        '"' => "DQT",
+        '<' => "LT",
+        '>' => "GT",
+        '?' => "QMARK",
+        _ => return None,
+    };
+    Some(code.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{custom_shift_layout, misfits_layout, test_options, Format};
+
+    #[test]
+    fn custom_shift() {
+        let golden = include_str!("../../tests/golden/custom-shift.zmk");
+        assert_eq!(render(&custom_shift_layout(), &test_options(Format::Zmk)), golden);
+    }
+
+    #[test]
+    fn misfits() {
+        let golden = include_str!("../../tests/golden/misfits.zmk");
+        assert_eq!(render(&misfits_layout(), &test_options(Format::Zmk)), golden);
+    }
+}
@@ -0,0 +1,404 @@
+/// Imports layouts from the file formats of other analysers and tools.
+///
+/// Layouts are placed on the rows of keygen's key map from the top left. Keys
+/// that an imported layout doesn't define are left as `*`, and space goes to
+/// the right thumb if the layout has no key for it. Shifted characters that a
+/// format doesn't specify are taken from a US ANSI keyboard.
+use serde_json::Value;
+
+use crate::export::xkb::KEYSYMS;
+use crate::layout::{self, Layout};
+use crate::layout_file::{self, LayoutInfo};
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Keygen,
+    Grid,
+    Json,
+    Kle,
+    Xkb,
+}
+
+impl Format {
+    pub fn from_name(s: &str) -> Option<Format> {
+        match s {
+            "keygen" => Some(Format::Keygen),
+            "grid" => Some(Format::Grid),
+            "json" => Some(Format::Json),
+            "kle" => Some(Format::Kle),
+            "xkb" => Some(Format::Xkb),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of a layout file from its contents. Text is only
+    /// taken for JSON if it parses as JSON, and for a grid if its rows have
+    /// the shape of one.
+    pub fn detect(s: &str) -> Result<Format> {
+        // Layout files without a header start with `[lower]`, like JSON arrays.
+        if layout_file::has_sections(s) {
+            return Ok(Format::Keygen);
+        }
+        let trimmed = s.trim_start();
+        let json = match trimmed.chars().next() {
+            Some('[') | Some('{') => serde_json::from_str(s).ok(),
+            _ => None,
+        };
+        if let Some(value) = json {
+            // KLE files are an array of rows, which are arrays themselves.
+            match value {
+                Value::Array(rows) if rows.iter().any(Value::is_array) => Ok(Format::Kle),
+                _ => Ok(Format::Json),
+            }
+        } else if s.contains("xkb_symbols") || s.contains("key <") {
+            Ok(Format::Xkb)
+        } else if layout_file::parse(s).is_ok() {
+            Ok(Format::Keygen)
+        } else if is_grid(&grid(s)) {
+            Ok(Format::Grid)
+        } else {
+            Err("Unrecognised layout format; pass '--layout-format' if it is one of keygen, grid, json, kle or xkb".into())
+        }
+    }
+}
+
+/// Reads a layout in the given format, or in the detected one if `format` is
+/// `None`.
+pub fn parse(s: &str, format: Option<Format>) -> Result<(Layout, LayoutInfo)> {
+    let format = match format {
+        Some(format) => format,
+        None => Format::detect(s)?,
+    };
+    let layout = match format {
+        Format::Keygen => return Ok(layout_file::parse(s)?),
+        Format::Grid => from_rows(grid(s))?,
+        Format::Json => {
+            let name = serde_json::from_str::<Value>(s)
+                .ok()
+                .and_then(|v| v.get("name")?.as_str().map(str::to_string));
+            let info = LayoutInfo { name, ..LayoutInfo::default() };
+            return Ok((json(s)?, info));
+        }
+        Format::Kle => kle(s)?,
+        Format::Xkb => xkb(s)?,
+    };
+    Ok((layout, LayoutInfo::default()))
+}
+
+// A key given by its unshifted and, if known, shifted character.
+type Key = (char, Option<char>);
+
+// First position and number of keys of every row of the key map.
+const ROWS: [(usize, usize); 4] = [(0, 11), (11, 11), (22, 10), (32, 4)];
+
+fn from_rows(rows: Vec<Vec<Key>>) -> Result<Layout> {
+    if rows.len() < 3 || rows.len() > ROWS.len() {
+        return Err(format!("Expected 3 rows of keys and optionally thumb keys, found {} rows", rows.len()).into());
+    }
+    let mut keys = Vec::new();
+    for (r, row) in rows.into_iter().enumerate() {
+        let (start, len) = ROWS[r];
+        if row.len() > len {
+            return Err(format!("Row {} has {} keys, but at most {} fit", r + 1, row.len(), len).into());
+        }
+        keys.extend(row.into_iter().enumerate().map(|(i, key)| (start + i, key)));
+    }
+    Ok(from_keys(keys))
+}
+
+fn from_keys(keys: Vec<(usize, Key)>) -> Layout {
+    let mut lower = ['*'; 36];
+    let mut upper = ['*'; 36];
+    for (pos, (c, shifted)) in keys {
+        lower[pos] = c;
+        upper[pos] = shifted.unwrap_or_else(|| layout::shifted(c));
+    }
+    if !lower.contains(&' ') && lower[33] == '*' {
+        lower[33] = ' ';
+        upper[33] = ' ';
+    }
+    Layout::from_layers(lower, upper)
+}
+
+// Rows of a plain grid, either one character per key or with keys separated by
+// whitespace. In the latter case lines with longer words, like the name line
+// of genkey files, are skipped, as are finger maps following the three rows.
+fn grid(s: &str) -> Vec<Vec<Key>> {
+    let lines: Vec<&str> = s
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with("//"))
+        .collect();
+    let spaced = lines.iter().any(|l| l.split_whitespace().count() > 1);
+    let mut rows: Vec<Vec<Key>> = lines
+        .iter()
+        .filter_map(|l| grid_row(l, spaced))
+        .collect();
+    if rows.len() > 3 && rows[3].len() > ROWS[3].1 {
+        rows.truncate(3);
+    }
+    rows
+}
+
+// Whether the rows look like a layout: three letter rows of at least ten keys
+// that fit the key map, and optionally the thumb keys.
+fn is_grid(rows: &[Vec<Key>]) -> bool {
+    (3..=ROWS.len()).contains(&rows.len())
+        && rows.iter().zip(ROWS.iter()).enumerate().all(|(r, (row, (_, len)))| {
+            row.len() <= *len && (r == 3 || row.len() >= 10)
+        })
+}
+
+fn grid_row(line: &str, spaced: bool) -> Option<Vec<Key>> {
+    if !spaced {
+        return Some(line.chars().map(|c| (c, None)).collect());
+    }
+    line.split_whitespace()
+        .map(|token| {
+            let mut chars = token.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some((c, None)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// JSON with the rows under "keys", "rows" or "layout", either as strings in
+// the grid format, as arrays of keys, or as an object mapping every character
+// to its "row" and "col", as used by genkey, oxeylyzer and cmini.
+fn json(s: &str) -> Result<Layout> {
+    let value: Value = serde_json::from_str(s).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let rows = match &value {
+        Value::Object(fields) => ["keys", "rows", "layout"].iter().find_map(|k| fields.get(*k)),
+        Value::Array(_) => Some(&value),
+        _ => None,
+    }
+    .ok_or("Expected the layout in a 'keys', 'rows' or 'layout' field")?;
+
+    match rows {
+        Value::Array(rows) => {
+            let lines = rows
+                .iter()
+                .map(|row| match row {
+                    Value::String(line) => Ok(line.clone()),
+                    Value::Array(keys) => keys
+                        .iter()
+                        .map(|key| key.as_str().ok_or("Keys must be strings"))
+                        .collect::<std::result::Result<Vec<_>, _>>()
+                        .map(|keys| keys.join(" ")),
+                    _ => Err("Rows must be strings or arrays"),
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            from_rows(grid(&lines.join("\n")))
+        }
+        Value::Object(keys) => {
+            let mut placed = Vec::new();
+            for (c, key) in keys {
+                let position = |field: &str| key.get(field).and_then(Value::as_u64).map(|n| n as usize);
+                let c = single_char(c).ok_or_else(|| format!("Invalid key '{}'", c))?;
+                let (row, col) = position("row")
+                    .zip(position("col"))
+                    .ok_or_else(|| format!("Key '{}' has no 'row' and 'col'", c))?;
+                match ROWS.get(row) {
+                    Some((start, len)) if col < *len => placed.push((start + col, (c, None))),
+                    _ => return Err(format!("Key '{}' at row {}, column {} doesn't fit", c, row, col).into()),
+                }
+            }
+            Ok(from_keys(placed))
+        }
+        _ => Err("Expected the rows of the layout as an array or object".into()),
+    }
+}
+
+// keyboard-layout-editor.com JSON as downloaded from the site: an array of
+// rows holding key labels and objects with key properties. The letter rows are
+// the last three rows with at least ten keys labelled with characters, so that
+// the number row of a full keyboard is skipped.
+fn kle(s: &str) -> Result<Layout> {
+    let value: Value = serde_json::from_str(s).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let rows = value.as_array().ok_or("Expected an array of rows")?;
+    let mut rows: Vec<Vec<Key>> = rows
+        .iter()
+        .filter_map(Value::as_array)
+        .map(|row| row.iter().filter_map(Value::as_str).filter_map(kle_key).collect::<Vec<_>>())
+        .filter(|row| row.len() >= 10)
+        .collect();
+    if rows.len() < 3 {
+        return Err("Expected at least three rows of keys labelled with characters".into());
+    }
+    let mut rows = rows.split_off(rows.len() - 3);
+    for (row, (_, len)) in rows.iter_mut().zip(ROWS.iter()) {
+        row.truncate(*len);
+    }
+    from_rows(rows)
+}
+
+// Legends of a KLE key are separated by newlines, starting top left, so keys
+// with two legends have the shifted character first.
+fn kle_key(label: &str) -> Option<Key> {
+    let legends: Vec<&str> = label.split('\n').collect();
+    let first = single_char(legends[0])?;
+    match legends.get(1).and_then(|l| single_char(l)) {
+        Some(lower) => Some((lower, Some(first))),
+        None if first.is_uppercase() => Some((first.to_lowercase().next()?, Some(first))),
+        None => Some((first, None)),
+    }
+}
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+// XKB symbols. Keys of the letter block and the space bar are read from the
+// first group; everything else is ignored.
+fn xkb(s: &str) -> Result<Layout> {
+    let mut keys = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("key <") {
+        rest = &rest[start + 5..];
+        let name = &rest[..rest.find('>').ok_or("Unterminated key name")?];
+        let end = rest.find(';').unwrap_or(rest.len());
+        let definition = &rest[..end];
+        rest = &rest[end..];
+
+        let pos = match xkb_position(name) {
+            Some(pos) => pos,
+            None => continue,
+        };
+        let symbols = match (definition.find('['), definition.find(']')) {
+            (Some(open), Some(close)) if open < close => &definition[open + 1..close],
+            _ => continue,
+        };
+        let mut symbols = symbols.split(',').map(|sym| keysym_char(sym.trim()));
+        match symbols.next() {
+            Some(Ok(Some(c))) => keys.push((pos, (c, symbols.next().transpose()?.flatten()))),
+            Some(Err(e)) => return Err(e),
+            _ => {}
+        }
+    }
+    if keys.is_empty() {
+        return Err("No keys of the letter block found".into());
+    }
+    Ok(from_keys(keys))
+}
+
+fn xkb_position(name: &str) -> Option<usize> {
+    if name == "SPCE" {
+        return Some(33);
+    }
+    let row = ["AD", "AC", "AB"].iter().position(|r| name.starts_with(r))?;
+    let col: usize = name[2..].parse().ok()?;
+    let (start, len) = ROWS[row];
+    if col >= 1 && col <= len {
+        Some(start + col - 1)
+    } else {
+        None
+    }
+}
+
+fn keysym_char(name: &str) -> Result<Option<char>> {
+    if name == "NoSymbol" || name == "VoidSymbol" {
+        return Ok(None);
+    }
+    if let Some(c) = single_char(name) {
+        return Ok(Some(c));
+    }
+    if let Some((c, _)) = KEYSYMS.iter().find(|(_, n)| *n == name) {
+        return Ok(Some(*c));
+    }
+    name.strip_prefix('U')
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .and_then(std::char::from_u32)
+        .map(Some)
+        .ok_or_else(|| format!("Unsupported keysym '{}'", name).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Both layers of a layout as strings.
+    fn layers(layout: &Layout) -> (String, String) {
+        (layout.lower().iter().collect(), layout.upper().iter().collect())
+    }
+
+    fn detect_and_parse(s: &str, format: Format) -> (Layout, LayoutInfo) {
+        assert_eq!(Format::detect(s).unwrap(), format);
+        parse(s, None).unwrap()
+    }
+
+    #[test]
+    fn genkey_grid() {
+        let (layout, _) = detect_and_parse(include_str!("../tests/fixtures/colemak-dh.genkey"), Format::Grid);
+        assert_eq!(layers(&layout), layers(&layout::COLEMAK_DH_LAYOUT));
+    }
+
+    #[test]
+    fn cmini_json() {
+        let (layout, info) = detect_and_parse(include_str!("../tests/fixtures/colemak.cmini.json"), Format::Json);
+        assert_eq!(info.name.as_deref(), Some("Colemak"));
+        assert_eq!(
+            layers(&layout),
+            (
+                "qwfpgjluy;*arstdhneio'zxcvbkm,./* **".to_string(),
+                "QWFPGJLUY:*ARSTDHNEIO\"ZXCVBKM<>?* **".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn kle_ansi_104() {
+        let (layout, _) = detect_and_parse(include_str!("../tests/fixtures/ansi-104.kle.json"), Format::Kle);
+        assert_eq!(
+            layers(&layout),
+            (
+                "qwertyuiop[asdfghjkl;'zxcvbnm,./* **".to_string(),
+                "QWERTYUIOP{ASDFGHJKL:\"ZXCVBNM<>?* **".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn xkeyboard_config_colemak() {
+        let (layout, _) = detect_and_parse(include_str!("../tests/fixtures/colemak.xkb"), Format::Xkb);
+        assert_eq!(
+            layers(&layout),
+            (
+                "qwfpgjluy;[arstdhneio'zxcvbkm,./* **".to_string(),
+                "QWFPGJLUY:{ARSTDHNEIO\"ZXCVBKM<>?* **".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn headerless_layout_file() {
+        let (layout, info) = detect_and_parse(include_str!("../tests/fixtures/colemak-dh.layout"), Format::Keygen);
+        assert_eq!(layers(&layout), layers(&layout::COLEMAK_DH_LAYOUT));
+        assert_eq!(info, LayoutInfo::default());
+    }
+
+    #[test]
+    fn layout_files_round_trip() {
+        for layout in [&layout::QWERTY_LAYOUT, crate::penalty::INIT_LAYOUT] {
+            let s = layout_file::format(layout, &LayoutInfo::default());
+            assert_eq!(&parse(&s, None).unwrap().0, layout);
+            let info = LayoutInfo { name: Some("Test".to_string()), ..LayoutInfo::default() };
+            let s = layout_file::format(layout, &info);
+            assert_eq!(parse(&s, None).unwrap(), (layout.clone(), info));
+        }
+    }
+
+    #[test]
+    fn unrecognised_format() {
+        for s in &["", "Not a layout at all,\njust a few lines\nof prose.\n", "abc\ndef\nghi\n", "[lower, upper"] {
+            assert!(Format::detect(s).is_err(), "{:?} was detected as a layout", s);
+            assert!(parse(s, None).is_err());
+        }
+    }
+}
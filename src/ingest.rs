@@ -0,0 +1,559 @@
+/// Streams text corpora into n-gram counts, so that corpora of any size can
+/// be used without holding them in memory. N-grams are as long as the context,
+/// except that the first keys of a run of typable characters end shorter ones
+/// once there are as many as every penalty sees.
+///
+/// Files are read in chunks of whole lines, which are preprocessed before
+/// counting; n-grams spanning two chunks are counted like any other, while a
+/// file's last characters never form an n-gram with the next file.
+/// Directories are read recursively, and files ending in `.keylog` are read as
+/// keystroke logs, see `keylog`. N-grams are only counted if the default
+/// layout can type all of their characters.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+use rayon::prelude::*;
+
+use crate::keylog;
+use crate::layout::LayoutPosMap;
+use crate::penalty::{Corpus, NGram, DEFAULT_CONTEXT, INIT_LAYOUT, MAX_CONTEXT};
+use crate::preprocess::Preprocess;
+use crate::sampling::Sampling;
+use crate::Result;
+
+const CHUNK_SIZE: usize = 1 << 20;
+// Lines longer than this are processed in parts.
+const MAX_LINE: usize = 16 << 20;
+
+struct Counter<'a> {
+    preprocess: &'a Preprocess,
+    sampling: &'a Sampling,
+    // Buffer for preprocessed lines, and whether its last character was a
+    // space.
+    line: String,
+    space: bool,
+    // Number of bytes read at once and the length from which lines are
+    // processed in parts, which tests make small.
+    chunk_size: usize,
+    max_line: usize,
+    position_map: LayoutPosMap,
+    // Length of the counted n-grams, and the last typable characters, oldest
+    // first; `window_len` of them are valid.
+    context: usize,
+    window: [char; MAX_CONTEXT],
+    window_len: usize,
+    // Counts of every bin of the sampling, the current bin and the position
+    // in the corpus that decides it.
+    counts: Vec<HashMap<NGram, f64>>,
+    bin: usize,
+    file: usize,
+    line_number: usize,
+    // Occurrences of every character, ASCII ones counted separately as they
+    // are by far the most common.
+    ascii_chars: [f64; 128],
+    chars: HashMap<char, f64>,
+    stats: Option<TextStats>,
+}
+
+/// Statistics of the text a corpus is read from, after preprocessing.
+#[derive(Default)]
+pub struct TextStats {
+    // Number of n-grams in the text, whether the layout can type them or not,
+    // and of those dropped because it can't; every character ends one if it
+    // has enough others before it. Also the length of the current run.
+    pub ngrams: f64,
+    pub dropped: f64,
+    run: usize,
+}
+
+impl<'a> Counter<'a> {
+    fn new(preprocess: &'a Preprocess, sampling: &'a Sampling, context: usize) -> Counter<'a> {
+        Counter {
+            preprocess,
+            sampling,
+            line: String::new(),
+            space: false,
+            chunk_size: CHUNK_SIZE,
+            max_line: MAX_LINE,
+            position_map: INIT_LAYOUT.get_position_map(),
+            context,
+            window: ['\0'; MAX_CONTEXT],
+            window_len: 0,
+            counts: vec![HashMap::new(); sampling.bins()],
+            bin: 0,
+            file: 0,
+            line_number: 0,
+            ascii_chars: [0.0; 128],
+            chars: HashMap::new(),
+            stats: None,
+        }
+    }
+
+    fn feed(&mut self, s: &str) {
+        for c in s.chars() {
+            self.feed_char(c, 1.0);
+        }
+    }
+
+    // Counts a character, with the n-gram it ends counting `weight` times.
+    fn feed_char(&mut self, c: char, weight: f64) {
+        match self.ascii_chars.get_mut(c as usize) {
+            Some(count) => *count += 1.0,
+            None => *self.chars.entry(c).or_insert(0.0) += 1.0,
+        }
+        if self.position_map.get_key_position(c).is_some() {
+            let window = &mut self.window[..self.context];
+            window.rotate_left(1);
+            window[self.context - 1] = c;
+            self.window_len = (self.window_len + 1).min(self.context);
+        } else {
+            self.window_len = 0;
+        }
+        if let Some(stats) = &mut self.stats {
+            stats.run += 1;
+            if stats.run >= DEFAULT_CONTEXT {
+                stats.ngrams += 1.0;
+                if self.window_len < DEFAULT_CONTEXT {
+                    stats.dropped += 1.0;
+                }
+            }
+        }
+        if self.window_len < DEFAULT_CONTEXT {
+            return;
+        }
+        let window = &self.window[self.context - self.window_len..self.context];
+        // Typable characters are ASCII.
+        if let Some(ngram) = NGram::new(window) {
+            *self.counts[self.bin].entry(ngram).or_insert(0.0) += weight * self.preprocess.weight(window);
+        }
+    }
+
+    fn break_ngrams(&mut self) {
+        self.window_len = 0;
+        if let Some(stats) = &mut self.stats {
+            stats.run = 0;
+        }
+    }
+
+    // N-grams don't span lines of different bins.
+    fn enter_bin(&mut self, bin: usize) {
+        if bin != self.bin {
+            self.break_ngrams();
+            self.bin = bin;
+        }
+    }
+
+    fn feed_line(&mut self, line: &str) {
+        if self.sampling.is_active() {
+            self.enter_bin(self.sampling.bin(self.file, self.line_number));
+            self.line_number += 1;
+        }
+        if self.preprocess.is_identity() {
+            self.feed(line);
+            return;
+        }
+        let mut processed = std::mem::take(&mut self.line);
+        processed.clear();
+        if self.preprocess.line(line, &mut processed, &mut self.space) {
+            self.feed(&processed);
+        } else {
+            self.break_ngrams();
+        }
+        self.line = processed;
+    }
+
+    // Invalid UTF-8 breaks n-grams like an untypable character.
+    fn feed_bytes(&mut self, bytes: &[u8]) {
+        let text = String::from_utf8_lossy(bytes);
+        for line in text.split_inclusive('\n') {
+            self.feed_line(line);
+        }
+    }
+
+    // Reads the `file`th file of the corpus.
+    fn read_file(&mut self, path: &Path, file: usize) -> Result<()> {
+        self.file = file;
+        self.line_number = 0;
+        if path.extension().and_then(|e| e.to_str()) == Some(keylog::EXTENSION) {
+            return self.read_keylog(path);
+        }
+        let mut file = File::open(path).map_err(|e| format!("Can't read corpus {}: {}", path.display(), e))?;
+        let mut buffer = vec![0; self.chunk_size];
+        // Bytes after the last complete line read so far.
+        let mut rest = Vec::new();
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            rest.extend_from_slice(&buffer[..read]);
+            let end = match rest.iter().rposition(|&b| b == b'\n') {
+                Some(pos) => pos + 1,
+                None if rest.len() > self.max_line => complete_len(&rest),
+                None => continue,
+            };
+            self.feed_bytes(&rest[..end]);
+            rest.drain(..end);
+        }
+        self.feed_bytes(&rest);
+        self.break_ngrams();
+        self.space = false;
+        Ok(())
+    }
+
+    // Keystroke logs are small enough to be read at once.
+    fn read_keylog(&mut self, path: &Path) -> Result<()> {
+        let s = std::fs::read_to_string(path).map_err(|e| format!("Can't read corpus {}: {}", path.display(), e))?;
+        let strokes = keylog::parse(&s, self.preprocess)
+            .map_err(|e| format!("Invalid keystroke log {}: {}", path.display(), e))?;
+        let mean = keylog::mean_latency(&strokes);
+        // Keystroke logs have no lines and are split by file.
+        self.enter_bin(self.sampling.bin(self.file, 0));
+        for stroke in strokes {
+            match (stroke.c, stroke.latency) {
+                (Some(c), Some(latency)) if self.preprocess.latency_weights && mean > 0.0 => {
+                    self.feed_char(c, latency / mean)
+                }
+                (Some(c), _) => self.feed_char(c, 1.0),
+                (None, _) => self.break_ngrams(),
+            }
+        }
+        self.break_ngrams();
+        Ok(())
+    }
+
+    fn into_corpus(self) -> Corpus {
+        let mut bins = self.counts.into_iter().map(Corpus::from_counts).collect::<Vec<_>>();
+        let mut corpus = if self.sampling.is_active() {
+            self.sampling.assemble(bins)
+        } else {
+            bins.swap_remove(0)
+        };
+        corpus.chars = self.chars;
+        for (c, count) in self.ascii_chars.iter().enumerate() {
+            if *count > 0.0 {
+                corpus.chars.insert(c as u8 as char, *count);
+            }
+        }
+        corpus
+    }
+}
+
+// Length of `bytes` without a UTF-8 character cut off at the end.
+fn complete_len(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let b = bytes[bytes.len() - back];
+        if b & 0xc0 == 0xc0 {
+            let width = if b >= 0xf0 { 4 } else if b >= 0xe0 { 3 } else { 2 };
+            return if back < width { bytes.len() - back } else { bytes.len() };
+        }
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+    bytes.len()
+}
+
+/// Counts the n-grams of a text file, or of all files below a directory,
+/// counting several files at once if `parallel` is set.
+pub fn read_path(
+    path: &Path,
+    parallel: bool,
+    preprocess: &Preprocess,
+    sampling: &Sampling,
+    context: usize,
+) -> Result<Corpus> {
+    let files = collect_files(path, preprocess)?;
+    if parallel {
+        let corpus = files
+            .par_iter()
+            .enumerate()
+            .map(|(n, file)| {
+                let mut counter = Counter::new(preprocess, sampling, context);
+                counter.read_file(file, n).map_err(|e| e.to_string())?;
+                Ok::<_, String>(counter.into_corpus())
+            })
+            .try_reduce(Corpus::default, |mut a, b| {
+                a.merge(&b);
+                Ok(a)
+            })?;
+        return Ok(corpus);
+    }
+    let mut counter = Counter::new(preprocess, sampling, context);
+    for (n, file) in files.iter().enumerate() {
+        counter.read_file(file, n)?;
+    }
+    Ok(counter.into_corpus())
+}
+
+fn collect_files(path: &Path, preprocess: &Preprocess) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    match &preprocess.code {
+        Some(code) if path.is_dir() => {
+            let walk = WalkBuilder::new(path)
+                .require_git(false)
+                .sort_by_file_name(|a, b| a.cmp(b))
+                .build();
+            for entry in walk {
+                let entry = entry.map_err(|e| format!("Can't read corpus directory {}: {}", path.display(), e))?;
+                if entry.file_type().is_some_and(|t| t.is_file()) && code.includes(entry.path()) {
+                    files.push(entry.into_path());
+                }
+            }
+        }
+        _ => add_files(path, &mut files)?,
+    }
+    Ok(files)
+}
+
+fn add_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)
+        .map_err(|e| format!("Can't read corpus directory {}: {}", path.display(), e))?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    for entry in entries {
+        add_files(&entry, files)?;
+    }
+    Ok(())
+}
+
+/// Like `read_path`, but also returns statistics of the text, which are
+/// gathered from the files one after the other.
+pub fn read_path_with_stats(path: &Path, preprocess: &Preprocess, context: usize) -> Result<(Corpus, TextStats)> {
+    let sampling = Sampling::default();
+    let mut counter = Counter::new(preprocess, &sampling, context);
+    counter.stats = Some(TextStats::default());
+    for (n, file) in collect_files(path, preprocess)?.iter().enumerate() {
+        counter.read_file(file, n)?;
+    }
+    let stats = counter.stats.take().unwrap_or_default();
+    Ok((counter.into_corpus(), stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Grüße aus Köln, the quick brown fox 🦊 jumps over\nthe lazy dog.\n\n\
+                        Pack my box with five dozen liquor jugs — at once\r\nno newline at the end";
+
+    // N-grams of `text` with their counts, read as a single line.
+    fn count(text: &str, context: usize) -> Vec<(String, f64)> {
+        let (preprocess, sampling) = (Preprocess::default(), Sampling::default());
+        let mut counter = Counter::new(&preprocess, &sampling, context);
+        counter.feed_line(text);
+        sorted(&counter.into_corpus())
+    }
+
+    // N-grams and characters of a corpus with their counts, sorted.
+    fn sorted(corpus: &Corpus) -> Vec<(String, f64)> {
+        let mut counts: Vec<_> = corpus.ngrams.iter().map(|(n, c)| (n.as_str().to_string(), *c)).collect();
+        counts.extend(corpus.chars.iter().map(|(c, count)| (format!("char {:?}", c), *count)));
+        counts.sort_by(|a, b| a.0.cmp(&b.0));
+        counts
+    }
+
+    // A new directory for the files of a test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keygen-ingest-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // The given n-grams, each counted once, and the characters of `text`.
+    fn ngrams(text: &str, ngrams: &[&str]) -> Vec<(String, f64)> {
+        let mut counts: Vec<_> = ngrams.iter().map(|n| (n.to_string(), 1.0)).collect();
+        let mut chars: Vec<char> = text.chars().collect();
+        chars.sort_unstable();
+        chars.dedup();
+        for c in chars {
+            counts.push((format!("char {:?}", c), text.chars().filter(|&d| d == c).count() as f64));
+        }
+        counts.sort_by(|a, b| a.0.cmp(&b.0));
+        counts
+    }
+
+    #[test]
+    fn ngrams_need_four_typable_keys() {
+        assert_eq!(count("abcdef", 4), ngrams("abcdef", &["abcd", "bcde", "cdef"]));
+        // Digits and newlines can't be typed and break n-grams.
+        assert_eq!(count("ab1cdefg\nhij", 4), ngrams("ab1cdefg\nhij", &["cdef", "defg"]));
+    }
+
+    #[test]
+    fn dropped_ngrams() {
+        let (preprocess, sampling) = (Preprocess::default(), Sampling::default());
+        let mut counter = Counter::new(&preprocess, &sampling, 4);
+        counter.stats = Some(TextStats::default());
+        // The digit breaks "ab1c", "b1cd" and "1cde"; "xyz" is too short.
+        counter.feed_line("ab1cdefg");
+        counter.break_ngrams();
+        counter.feed_line("xyz");
+        let stats = counter.stats.take().unwrap();
+        assert_eq!((stats.ngrams, stats.dropped), (5.0, 3.0));
+        assert_eq!(counter.into_corpus().len, 2.0);
+    }
+
+    #[test]
+    fn shorter_ngrams_start_runs_at_longer_contexts() {
+        assert_eq!(count("abcdef", 5), ngrams("abcdef", &["abcd", "abcde", "bcdef"]));
+        assert_eq!(count("abcdefg", 6), ngrams("abcdefg", &["abcd", "abcde", "abcdef", "bcdefg"]));
+    }
+
+    #[test]
+    fn chunk_boundaries() {
+        let dir = temp_dir("chunks");
+        let path = dir.join("text.txt");
+        std::fs::write(&path, TEXT).unwrap();
+        let (preprocess, sampling) = (Preprocess::default(), Sampling::default());
+        let read = |chunk_size, max_line| {
+            let mut counter = Counter::new(&preprocess, &sampling, 4);
+            counter.chunk_size = chunk_size;
+            counter.max_line = max_line;
+            counter.read_file(&path, 0).unwrap();
+            sorted(&counter.into_corpus())
+        };
+        let whole = read(CHUNK_SIZE, MAX_LINE);
+        assert!(whole.contains(&("jugs".to_string(), 1.0)));
+        assert!(whole.contains(&("char 'ü'".to_string(), 1.0)));
+        // Small lengths split lines, and characters, between chunks.
+        for chunk_size in [1, 2, 3, 5, 64] {
+            for max_line in [MAX_LINE, 1, 2, 7] {
+                assert_eq!(read(chunk_size, max_line), whole, "chunks of {}, lines of {}", chunk_size, max_line);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn incomplete_characters() {
+        let s = "aü🦊".as_bytes();
+        assert_eq!(complete_len(&s[..1]), 1);
+        assert_eq!(complete_len(&s[..2]), 1);
+        assert_eq!(complete_len(&s[..3]), 3);
+        for len in 4..7 {
+            assert_eq!(complete_len(&s[..len]), 3);
+        }
+        assert_eq!(complete_len(s), 7);
+        assert_eq!(complete_len(&[]), 0);
+    }
+
+    #[test]
+    fn parallel_reading() {
+        let dir = temp_dir("parallel");
+        for (i, line) in TEXT.lines().enumerate() {
+            std::fs::create_dir_all(dir.join(format!("dir{}", i % 2))).unwrap();
+            std::fs::write(dir.join(format!("dir{}/{}.txt", i % 2, i)), line.repeat(3)).unwrap();
+        }
+        let (preprocess, sampling) = (Preprocess::default(), Sampling::default());
+        for context in [4, 6] {
+            let sequential = read_path(&dir, false, &preprocess, &sampling, context).unwrap();
+            let parallel = read_path(&dir, true, &preprocess, &sampling, context).unwrap();
+            assert_eq!(sorted(&parallel), sorted(&sequential));
+            assert_eq!(parallel.len, sequential.len);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn code_directories() {
+        let dir = temp_dir("code");
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        std::fs::write(dir.join("src/main.rs"), "    let x;\n").unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "").unwrap();
+        std::fs::write(dir.join("README.md"), "").unwrap();
+        std::fs::write(dir.join("target/out.rs"), "").unwrap();
+
+        let code = Preprocess {
+            code: Some(crate::preprocess::Code {
+                extensions: vec!["rs".to_string(), "md".to_string()],
+                autopairs: false,
+                identifier_weight: 2.0,
+                punctuation_weight: 1.0,
+            }),
+            ..Preprocess::default()
+        };
+        let files = |preprocess: &Preprocess| -> Vec<String> {
+            collect_files(&dir, preprocess)
+                .unwrap()
+                .iter()
+                .map(|file| file.strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(files(&code), ["README.md", "src/lib.rs", "src/main.rs"]);
+        assert_eq!(
+            files(&Preprocess::default()),
+            [".gitignore", "README.md", "src/lib.rs", "src/main.rs", "target/out.rs"]
+        );
+
+        // Indentation is stripped and identifier n-grams are weighted.
+        let file = dir.join("src/main.rs");
+        let sampling = Sampling::default();
+        let corpus = read_path(&file, false, &code, &sampling, 4).unwrap();
+        assert_eq!(corpus.chars[&' '], 1.0);
+        let counts: Vec<_> = sorted(&corpus).into_iter().filter(|(n, _)| !n.starts_with("char ")).collect();
+        assert_eq!(counts, [("et x".to_string(), 2.0), ("let ".to_string(), 2.0), ("t x;".to_string(), 1.0)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keylog_latency_weights() {
+        let dir = temp_dir("keylog");
+        let path = dir.join("sample.keylog");
+        std::fs::write(&path, include_str!("../tests/fixtures/sample.keylog")).unwrap();
+        let sampling = Sampling::default();
+        let read = |preprocess: &Preprocess| {
+            let corpus = read_path(&path, false, preprocess, &sampling, 4).unwrap();
+            let mut counts: Vec<_> = corpus.ngrams.iter().map(|(n, c)| (n.as_str().to_string(), *c)).collect();
+            counts.sort_by(|a, b| a.0.cmp(&b.0));
+            (counts, corpus.chars)
+        };
+
+        let (counts, chars) = read(&Preprocess::default());
+        assert_eq!(counts, ngrams("", &[" cat", "The ", "e ca", "he c"]));
+        assert_eq!(chars[&keylog::BACKSPACE], 1.0);
+        assert_eq!(chars[&'t'], 1.0);
+
+        // Every n-gram ends in a keystroke 100 ms after the previous one, and
+        // the mean latency is 690 / 7 ms.
+        let (counts, _) = read(&Preprocess { latency_weights: true, ..Preprocess::default() });
+        let weight = 100.0 / (690.0 / 7.0);
+        let weighted: Vec<_> = ngrams("", &[" cat", "The ", "e ca", "he c"]).into_iter().map(|(n, _)| (n, weight)).collect();
+        assert_eq!(counts, weighted);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn held_out_lines() {
+        let dir = temp_dir("holdout");
+        let path = dir.join("text.txt");
+        std::fs::write(&path, TEXT).unwrap();
+        let preprocess = Preprocess::default();
+        let all = read_path(&path, false, &preprocess, &Sampling::default(), 4).unwrap();
+        let read = |sampling: &Sampling| read_path(&path, false, &preprocess, sampling, 4).unwrap();
+
+        let corpus = read(&Sampling { holdout: 1.0, ..Sampling::default() });
+        assert_eq!(corpus.len, 0.0);
+        assert_eq!(corpus.held_out.as_ref().unwrap().ngrams, all.ngrams);
+        assert_eq!(corpus.chars, all.chars);
+
+        // Every line is in one part, and n-grams don't span lines anyway.
+        let corpus = read(&Sampling { holdout: 0.5, bootstrap: 10, ..Sampling::default() });
+        let held_out = corpus.held_out.as_ref().unwrap();
+        assert_eq!(corpus.len + held_out.len, all.len);
+        let blocks = &corpus.bootstrap.as_ref().unwrap().blocks;
+        assert_eq!(blocks.iter().map(|block| block.len).sum::<f64>(), corpus.len);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
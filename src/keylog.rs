@@ -0,0 +1,203 @@
+/// Keystroke logs, which can be used as a corpus of what was actually typed,
+/// corrections included, rather than of the resulting text.
+///
+/// A log has a line per key event with a timestamp in milliseconds, the key
+/// and `down` or `up`, separated by tabs (shown as spaces here); `#` starts a
+/// comment line:
+///
+/// ```text
+/// 1000.0  shift  down
+/// 1080.5  t  down
+/// 1121.0  shift  up
+/// 1150.2  t  up
+/// 1210.0  backspace  down
+/// ```
+///
+/// Keys are named by the character they type without shift, or as `space`,
+/// `enter`, `tab`, `backspace` or `shift` (also `lshift` and `rshift`). Keys
+/// pressed with shift type their shifted character. Backspaces are kept as a
+/// character the layout can't type. Any other named key breaks n-grams, as
+/// do keys pressed while one is held (shortcuts such as ctrl-c) and pauses.
+use crate::layout::shifted;
+use crate::preprocess::Preprocess;
+use crate::Result;
+
+/// Files with this extension are read as keystroke logs.
+pub const EXTENSION: &str = "keylog";
+
+/// How backspaces are counted.
+pub const BACKSPACE: char = '\u{8}';
+
+// Pauses longer than this, in milliseconds, break n-grams.
+const MAX_LATENCY: f64 = 2000.0;
+
+pub struct Stroke {
+    // The character typed, or `None` if n-grams are broken here.
+    pub c: Option<char>,
+    // Milliseconds since the previous keystroke, unless n-grams were broken
+    // in between.
+    pub latency: Option<f64>,
+}
+
+enum Key {
+    Char(char),
+    Shift,
+    Other,
+}
+
+fn parse_key(name: &str) -> Key {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Key::Char(c);
+    }
+    match name.to_ascii_lowercase().as_str() {
+        "space" => Key::Char(' '),
+        "enter" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "backspace" => Key::Char(BACKSPACE),
+        "shift" | "lshift" | "rshift" => Key::Shift,
+        _ => Key::Other,
+    }
+}
+
+/// Reads the keystrokes of a log. Of the preprocessing options, only case
+/// folding, stripping digits and typing newlines and tabs as another key
+/// apply: the log records exactly what was typed.
+pub fn parse(s: &str, preprocess: &Preprocess) -> Result<Vec<Stroke>> {
+    let mut strokes = Vec::new();
+    // Keys currently held, and the time of the last keystroke.
+    let mut held: Vec<&str> = Vec::new();
+    let mut last: Option<f64> = None;
+    let mut time = f64::NEG_INFINITY;
+    for (n, line) in s.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (t, key, event) = match line.split('\t').collect::<Vec<_>>()[..] {
+            [t, key, event] => (t, key, event),
+            _ => return Err(format!("line {}: expected a timestamp, a key and an event separated by tabs", n + 1).into()),
+        };
+        let t = match t.trim().parse::<f64>() {
+            Ok(t) if t.is_finite() && t >= time => t,
+            _ => return Err(format!("line {}: invalid timestamp '{}'", n + 1, t).into()),
+        };
+        time = t;
+        match event.trim() {
+            "up" => {
+                held.retain(|k| *k != key);
+                continue;
+            }
+            "down" if held.contains(&key) => continue, // Autorepeat
+            "down" => held.push(key),
+            _ => return Err(format!("line {}: invalid event '{}', expected 'down' or 'up'", n + 1, event).into()),
+        }
+
+        let shift = held.iter().any(|k| matches!(parse_key(k), Key::Shift));
+        let chord = held.iter().any(|k| matches!(parse_key(k), Key::Other));
+        let c = match parse_key(key) {
+            Key::Shift => continue,
+            Key::Other => None,
+            Key::Char(_) if chord => None,
+            Key::Char('\n') => Some(preprocess.newline.unwrap_or('\n')),
+            Key::Char('\t') => Some(preprocess.tab.unwrap_or('\t')),
+            Key::Char(c) if preprocess.strip_digits && c.is_ascii_digit() => Some('\0'),
+            Key::Char(c) if shift && !preprocess.fold_case => Some(shifted(c)),
+            Key::Char(c) if preprocess.fold_case => Some(c.to_lowercase().next().unwrap_or(c)),
+            Key::Char(c) => Some(c),
+        };
+        let latency = last.map(|last| t - last).filter(|&latency| latency <= MAX_LATENCY);
+        if last.is_some() && latency.is_none() {
+            strokes.push(Stroke { c: None, latency: None });
+        }
+        last = c.map(|_| t);
+        strokes.push(Stroke { c, latency: c.and(latency) });
+    }
+    Ok(strokes)
+}
+
+/// Mean latency of the keystrokes that have one.
+pub fn mean_latency(strokes: &[Stroke]) -> f64 {
+    let latencies: Vec<f64> = strokes.iter().filter_map(|stroke| stroke.latency).collect();
+    if latencies.is_empty() {
+        return 0.0;
+    }
+    latencies.iter().sum::<f64>() / latencies.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = include_str!("../tests/fixtures/sample.keylog");
+
+    fn strokes(s: &str, preprocess: &Preprocess) -> Vec<(Option<char>, Option<f64>)> {
+        parse(s, preprocess).unwrap().into_iter().map(|stroke| (stroke.c, stroke.latency)).collect()
+    }
+
+    fn error(s: &str) -> String {
+        match parse(s, &Preprocess::default()) {
+            Ok(_) => panic!("{:?} was read as a keystroke log", s),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn keystrokes() {
+        assert_eq!(
+            strokes(SAMPLE, &Preprocess::default()),
+            [
+                (Some('T'), None),
+                (Some('h'), Some(90.0)),
+                // The second and third 'e' are autorepeat.
+                (Some('e'), Some(100.0)),
+                (Some(' '), Some(100.0)),
+                (Some('c'), Some(100.0)),
+                (Some('a'), Some(100.0)),
+                (Some('t'), Some(100.0)),
+                // ctrl-c
+                (None, None),
+                (None, None),
+                (Some('x'), None),
+                // The pause
+                (None, None),
+                (Some('y'), None),
+                (Some(BACKSPACE), Some(100.0)),
+            ]
+        );
+        assert_eq!(mean_latency(&parse(SAMPLE, &Preprocess::default()).unwrap()), 690.0 / 7.0);
+        assert_eq!(mean_latency(&[]), 0.0);
+    }
+
+    #[test]
+    fn preprocessing() {
+        let preprocess = Preprocess {
+            fold_case: true,
+            strip_digits: true,
+            newline: Some(' '),
+            tab: Some('-'),
+            ..Preprocess::default()
+        };
+        let log = "0\tshift\tdown\n1\tt\tdown\n2\t1\tdown\n3\tenter\tdown\n4\ttab\tdown\n";
+        let chars: Vec<Option<char>> = strokes(log, &preprocess).into_iter().map(|(c, _)| c).collect();
+        assert_eq!(chars, [Some('t'), Some('\0'), Some(' '), Some('-')]);
+        let chars: Vec<Option<char>> = strokes(log, &Preprocess::default()).into_iter().map(|(c, _)| c).collect();
+        assert_eq!(chars, [Some('T'), Some('!'), Some('\n'), Some('\t')]);
+    }
+
+    #[test]
+    fn line_endings_and_named_keys() {
+        let log = "0\tSpace\tdown\r\n1\tRShift\tdown\r\n2\t;\tdown\r\n";
+        assert_eq!(strokes(log, &Preprocess::default()), [(Some(' '), None), (Some(':'), Some(2.0))]);
+    }
+
+    #[test]
+    fn invalid_logs() {
+        assert_eq!(error("0\tt\n"), "line 1: expected a timestamp, a key and an event separated by tabs");
+        assert_eq!(error("# comment\n0 t down\n"), "line 2: expected a timestamp, a key and an event separated by tabs");
+        assert_eq!(error("x\tt\tdown\n"), "line 1: invalid timestamp 'x'");
+        assert_eq!(error("inf\tt\tdown\n"), "line 1: invalid timestamp 'inf'");
+        assert_eq!(error("5\tt\tdown\n4\tt\tup\n"), "line 2: invalid timestamp '4'");
+        assert_eq!(error("0\tt\tpress\n"), "line 1: invalid event 'press', expected 'down' or 'up'");
+    }
+}
@@ -1,8 +1,8 @@
 #![allow(dead_code)]
-use crate::penalty;
+use crate::layout_file::{self, LayoutInfo};
 use crate::Result;
 use itertools::Itertools;
-use rand::{Rng, StdRng};
+use rand::Rng;
 /// Data structures and methods for creating and shuffling keyboard layouts.
 use std::fmt::{self, Display};
 use std::fs::File;
@@ -251,14 +251,14 @@ pub static CAPEWELL_LAYOUT: Layout = Layout(
     Layer(KeyMap([
         '.', 'y', 'w', 'd', 'f', 'j', 'p', 'l', 'u', 'q','*', //
         'a', 'e', 'r', 's', 'g', 'b', 't', 'n', 'i', 'o','*', //
-        'x', 'z', 'c', 'v', ';', 'k', 'w', 'h', ',', '/', //
+        'x', 'z', 'c', 'v', ';', 'k', 'm', 'h', ',', '/', //
         '*', ' ',
         '*', '*', 
     ])),
     Layer(KeyMap([
         '>', 'Y', 'W', 'D', 'F', 'J', 'P', 'L', 'U', 'Q','*', //
         'A', 'E', 'R', 'S', 'G', 'B', 'T', 'N', 'I', 'O', '*',//
-        'X', 'Z', 'C', 'V', ':', 'K', 'W', 'H', '<', '?', //
+        'X', 'Z', 'C', 'V', ':', 'K', 'M', 'H', '<', '?', //
         '*', ' ',
         '*', '*', 
     ])),
@@ -284,7 +284,7 @@ pub static ARENSITO_LAYOUT: Layout = Layout(
 static LAYOUT_MASK_NUM_SWAPPABLE: usize = 36;
 
 #[rustfmt::skip]
-static KEY_FINGERS: KeyMap<Finger> = KeyMap([
+pub static KEY_FINGERS: KeyMap<Finger> = KeyMap([
     Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index, Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
     Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index, Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
     Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index, Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, 
@@ -293,7 +293,7 @@ static KEY_FINGERS: KeyMap<Finger> = KeyMap([
 ]);
 
 #[rustfmt::skip]
-static KEY_HANDS: KeyMap<Hand> = KeyMap([
+pub static KEY_HANDS: KeyMap<Hand> = KeyMap([
     Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, 
     Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, 
     Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, 
@@ -319,15 +319,66 @@ static KEY_CENTER_COLUMN: KeyMap<bool> = KeyMap([
     false, false,
 ]);
 
+// Position of the top left corner of each key in key units, used for drawing
+// the layout. The halves are separated by one key unit.
 #[rustfmt::skip]
-static LAYOUT_FILE_IDXS: KeyMap<usize> = KeyMap([
-    0,  1,  2,  3,  4,  6,  7,  8,  9,  10, 11, 
-    14, 15, 16, 17, 18, 20, 21, 22, 23, 24,
-    25, 28, 29, 30, 31, 32, 34, 35, 36, 37, 38,
-    42, 44,
-    50, 52,
+pub static KEY_GEOMETRY: KeyMap<(f64, f64)> = KeyMap([
+    (0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (3.0, 0.0), (4.0, 0.0), (6.0, 0.0), (7.0, 0.0), (8.0, 0.0), (9.0, 0.0), (10.0, 0.0), (11.0, 0.0),
+    (0.0, 1.0), (1.0, 1.0), (2.0, 1.0), (3.0, 1.0), (4.0, 1.0), (6.0, 1.0), (7.0, 1.0), (8.0, 1.0), (9.0, 1.0), (10.0, 1.0), (11.0, 1.0),
+    (0.0, 2.0), (1.0, 2.0), (2.0, 2.0), (3.0, 2.0), (4.0, 2.0), (6.0, 2.0), (7.0, 2.0), (8.0, 2.0), (9.0, 2.0), (10.0, 2.0),
+                                                    (4.0, 3.2), (6.0, 3.2),
+                                                    (4.0, 4.2), (6.0, 4.2),
 ]);
 
+/// Unshifted and shifted characters of the non-letter keys of a US ANSI keyboard.
+pub const US_SHIFT_PAIRS: [(char, char); 21] = [
+    ('`', '~'),
+    ('1', '!'),
+    ('2', '@'),
+    ('3', '#'),
+    ('4', '$'),
+    ('5', '%'),
+    ('6', '^'),
+    ('7', '&'),
+    ('8', '*'),
+    ('9', '('),
+    ('0', ')'),
+    ('-', '_'),
+    ('=', '+'),
+    ('[', '{'),
+    (']', '}'),
+    ('\\', '|'),
+    (';', ':'),
+    ('\'', '"'),
+    (',', '<'),
+    ('.', '>'),
+    ('/', '?'),
+];
+
+/// Character that shift produces on the key of `c` on a US ANSI keyboard.
+pub fn us_shifted(c: char) -> Option<char> {
+    if c.is_ascii_lowercase() {
+        return Some(c.to_ascii_uppercase());
+    }
+    US_SHIFT_PAIRS
+        .iter()
+        .find(|(lower, _)| *lower == c)
+        .map(|(_, upper)| *upper)
+}
+
+/// Character that shift most likely produces on the key of `c`: the one of a US
+/// ANSI keyboard, the upper case of letters, and `c` itself otherwise.
+pub fn shifted(c: char) -> char {
+    if let Some(shifted) = us_shifted(c) {
+        return shifted;
+    }
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    }
+}
+
 impl From<&Layout> for LayoutPosMap {
     fn from(layout: &Layout) -> LayoutPosMap {
         let Layout(ref lower, ref upper) = *layout;
@@ -339,50 +390,30 @@ impl From<&Layout> for LayoutPosMap {
 }
 
 impl Layout {
-    #[rustfmt::skip]
-    pub fn write_to_file<P: AsRef<Path>>(&self, path: &P) -> Result<()> {
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: &P, info: &LayoutInfo) -> Result<()> {
         let mut file = File::create(path)?;
-
-        let lower = self.0.0.0;
-        let upper = self.1.0.0;
-        write!(file, "{}{}{}{}{} {}{}{}{}{}{}\n{}{}{}{}{} {}{}{}{}{}{}\n{}{}{}{}{} {}{}{}{}{}\n    {} {}\n    {} {}\n",
-            lower[0],  lower[1],  lower[2],  lower[3],  lower[4],  lower[5],  lower[6],  lower[7],  lower[8],  lower[9],
-            lower[10], lower[11], lower[12], lower[13], lower[14], lower[15], lower[16], lower[17], lower[18], lower[19],
-            lower[20], lower[21], lower[22], lower[23], lower[24], lower[25], lower[26], lower[27], lower[28], lower[29],
-            lower[30], lower[31],
-            lower[32], lower[33],
-            lower[34], lower[35],
-        )?;
-        write!(file, "{}{}{}{}{} {}{}{}{}{}{}\n{}{}{}{}{} {}{}{}{}{}{}\n{}{}{}{}{} {}{}{}{}{}\n    {} {}\n    {} {}\n",
-            upper[0],  upper[1],  upper[2],  upper[3],  upper[4],  upper[5],  upper[6],  upper[7],  upper[8],  upper[9],
-            upper[10], upper[11], upper[12], upper[13], upper[14], upper[15], upper[16], upper[17], upper[18], upper[19],
-            upper[20], upper[21], upper[22], upper[23], upper[24], upper[25], upper[26], upper[27], upper[28], upper[29],
-            upper[30], upper[31],
-            upper[32], upper[33],
-            upper[34], upper[35],
-        )?;
+        file.write_all(layout_file::format(self, info).as_bytes())?;
         Ok(())
     }
 
-    pub fn from_string(s: &str) -> Option<Layout> {
-        let s: Vec<char> = s.chars().collect();
-        let mut lower: [char; 36] = ['\0'; 36];
-        let mut upper: [char; 36] = ['\0'; 36];
+    pub fn from_layers(lower: [char; 36], upper: [char; 36]) -> Layout {
+        Layout(Layer(KeyMap(lower)), Layer(KeyMap(upper)))
+    }
 
-        for i in 0..36 {
-            let file_i = LAYOUT_FILE_IDXS.0[i];
-            lower[i] = *s.get(file_i)?;
-            upper[i] = *s.get(file_i + 55)?;
-            // println!("Got char (upper) {}", upper[i]);
-        }
+    pub fn lower(&self) -> &[char; 36] {
+        &((self.0).0).0
+    }
 
-        Some(Layout(Layer(KeyMap(lower)), Layer(KeyMap(upper))))
+    pub fn upper(&self) -> &[char; 36] {
+        &((self.1).0).0
     }
 
-    pub fn shuffle(&mut self, times: usize, rng: &mut StdRng) {
+    // Swaps `times` random pairs of keys, skipping pairs with a key that is
+    // not swappable according to `mask`.
+    pub fn shuffle<R: Rng>(&mut self, times: usize, mask: &KeyMap<bool>, rng: &mut R) {
         for _ in 0..times {
             let (i, j) = Layout::shuffle_position(rng);
-            if penalty::LAYOUT_MASK.0[i] && penalty::LAYOUT_MASK.0[j] {
+            if mask.0[i] && mask.0[j] {
                 let Layout(ref mut lower, ref mut upper) = *self;
                 lower.swap(i, j);
                 upper.swap(i, j);
@@ -399,7 +430,7 @@ impl Layout {
         LayoutPosMap(map)
     }
 
-    fn shuffle_position(rng: &mut StdRng) -> (usize, usize) {
+    fn shuffle_position<R: Rng>(rng: &mut R) -> (usize, usize) {
         let i = rng.gen_range(0, LAYOUT_MASK_NUM_SWAPPABLE);
         let mut j = rng.gen_range(0, LAYOUT_MASK_NUM_SWAPPABLE-1);
         if j >= i {
@@ -424,7 +455,7 @@ impl Layer {
         let KeyMap(ref centers) = KEY_CENTER_COLUMN;
         // Ignore null characters since non-existing keys are internally
         // represented by such.
-        for (i, c) in layer.into_iter().enumerate() {
+        for (i, c) in layer.iter().enumerate() {
             if (0 as char) < *c && *c < (128 as char) {
                 map[*c as usize] = Some(KeyPress {
                     kc: *c,
@@ -458,15 +489,22 @@ impl LayoutPermutations {
             swaps: std::iter::once((0, 0))
                 .chain(
                     (0..LAYOUT_MASK_NUM_SWAPPABLE)
-                        .map(|n| (0..n).zip(std::iter::repeat(n)))
-                        .flatten(),
+                        .flat_map(|n| (0..n).zip(std::iter::repeat(n))),
                 )
-                .filter(|(i, j)| penalty::LAYOUT_MASK.0[*i] && penalty::LAYOUT_MASK.0[*j])
+                .filter(|(i, j)| config.shuffle_mask.0[*i] && config.shuffle_mask.0[*j])
                 .collect(),
             swaps_per_iteration: config.swaps,
         }
     }
 
+    // Number of layouts yielded by `iter`.
+    pub fn len(&self) -> usize {
+        let n = self.swaps.len();
+        (0..self.swaps_per_iteration)
+            .map(|k| n.saturating_sub(k))
+            .product()
+    }
+
     pub fn set_layout(&mut self, layout: &Layout) {
         self.orig_layout = layout.clone();
     }
@@ -477,8 +515,8 @@ impl LayoutPermutations {
             .permutations(self.swaps_per_iteration)
             .map(move |perm: Vec<&(usize, usize)>| {
                 let mut layout = self.orig_layout.clone();
-                let ref mut lower = ((layout.0).0).0;
-                let ref mut upper = ((layout.1).0).0;
+                let lower = &mut ((layout.0).0).0;
+                let upper = &mut ((layout.1).0).0;
                 perm.iter().for_each(|(i, j)| {
                     lower.swap(*i, *j);
                     upper.swap(*i, *j);
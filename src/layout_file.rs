@@ -0,0 +1,479 @@
+/// Reading and writing of layout files.
+///
+/// A layout file starts with an optional header of `key: value` lines,
+/// followed by a `[lower]` and an optional `[upper]` section:
+///
+/// ```text
+/// # RSTHD with a pinned thumb key
+/// name: rsthd # the original
+/// pinned: 32
+///
+/// [lower]
+/// j c y f k  z l , u q \\
+/// r s t h d  m n a i o '
+/// / v g p b  x w . ; -
+///         e \s
+///         * *
+/// ```
+///
+/// In the header, `#` at the start of a line or after whitespace starts a
+/// comment; elsewhere it is part of the value, and `\#` escapes it. Comments
+/// are kept and written back at the top of the header.
+///
+/// Keys are separated by whitespace, so alignment doesn't matter. A section
+/// has rows of 11, 11 and 10 keys followed by two rows of two thumb keys and no
+/// comments, so `#` is a key like any other. `*` marks a key without a
+/// character. Whitespace and `\` are written as escapes: `\s` (space), `\n`,
+/// `\t`, `\\`, `\0` (a key that doesn't exist) or `\u{HEX}`; `\#` is read as
+/// `#` too. If the upper section is missing, it is derived from a US keyboard.
+use std::fmt;
+
+use crate::layout::{shifted, Layout};
+
+// Number of keys in every row of a section.
+const ROW_LENS: [usize; 5] = [11, 11, 10, 2, 2];
+
+/// Metadata from the header of a layout file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LayoutInfo {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub geometry: Option<String>,
+    // Positions whose keys are never moved by `run` and `refine`.
+    pub pinned: Vec<usize>,
+    // Comments of the header, without their `#`.
+    pub comments: Vec<String>,
+}
+
+impl LayoutInfo {
+    /// Metadata for layouts optimised from a layout with this metadata, which
+    /// keeps the geometry and pinned keys but not the name, author and
+    /// comments.
+    pub fn derived(&self) -> LayoutInfo {
+        LayoutInfo {
+            geometry: self.geometry.clone(),
+            pinned: self.pinned.clone(),
+            ..LayoutInfo::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn error<T>(line: usize, column: usize, message: String) -> Result<T, ParseError> {
+    Err(ParseError { line, column, message })
+}
+
+/// Parses a layout file. Files in the fixed-width format of older versions of
+/// keygen, which have no sections, are read as well.
+pub fn parse(s: &str) -> Result<(Layout, LayoutInfo), ParseError> {
+    if !has_sections(s) {
+        return match parse_legacy(s) {
+            Some(layout) => Ok((layout, LayoutInfo::default())),
+            None => error(1, 1, "expected a '[lower]' section".to_string()),
+        };
+    }
+
+    let mut info = LayoutInfo::default();
+    let mut lower: Option<[char; 36]> = None;
+    let mut upper: Option<[char; 36]> = None;
+    // Name of the current section and its rows so far.
+    let mut section: Option<(&str, usize)> = None;
+    let mut rows: Vec<(usize, Vec<(usize, char)>)> = Vec::new();
+
+    let lines: Vec<&str> = s.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        let n = i + 1;
+        let line = line.trim_end_matches('\r');
+
+        if let Some(name) = section_name(line) {
+            if let Some((name, start)) = section.take() {
+                let keys = section_keys(name, start, &rows)?;
+                store(name, start, keys, &mut lower, &mut upper)?;
+            }
+            if name != "lower" && name != "upper" {
+                return error(n, column_of(line, name) - 1, format!("unknown section '[{}]'", name));
+            }
+            section = Some((name, n));
+            rows.clear();
+            continue;
+        }
+
+        if section.is_some() {
+            let tokens = tokenize(line, n)?;
+            if !tokens.is_empty() {
+                rows.push((n, tokens));
+            }
+            continue;
+        }
+
+        let (content, comment) = split_comment(line);
+        if let Some(comment) = comment {
+            info.comments.push(comment.to_string());
+        }
+        if content.trim().is_empty() {
+            continue;
+        }
+        let (key, value) = match content.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => {
+                return error(
+                    n,
+                    column_of(line, content.trim()),
+                    "expected 'key: value' or a section".to_string(),
+                )
+            }
+        };
+        let text = || Some(value.replace("\\#", "#"));
+        match key {
+            "name" => info.name = text(),
+            "author" => info.author = text(),
+            "geometry" => info.geometry = text(),
+            "pinned" => {
+                for word in value.split_whitespace() {
+                    match word.parse::<usize>() {
+                        Ok(pos) if pos < 36 => info.pinned.push(pos),
+                        _ => {
+                            return error(
+                                n,
+                                column_of(line, word),
+                                format!("invalid position '{}', expected 0 to 35", word),
+                            )
+                        }
+                    }
+                }
+            }
+            _ => return error(n, column_of(line, key), format!("unknown header field '{}'", key)),
+        }
+    }
+    if let Some((name, start)) = section {
+        let keys = section_keys(name, start, &rows)?;
+        store(name, start, keys, &mut lower, &mut upper)?;
+    }
+
+    let lower = match lower {
+        Some(lower) => lower,
+        None => return error(lines.len().max(1), 1, "missing '[lower]' section".to_string()),
+    };
+    let upper = upper.unwrap_or_else(|| {
+        let mut upper = lower;
+        upper.iter_mut().for_each(|c| *c = shifted(*c));
+        upper
+    });
+    info.pinned.sort_unstable();
+    info.pinned.dedup();
+    Ok((Layout::from_layers(lower, upper), info))
+}
+
+/// Whether `s` is a layout file in the current format rather than the legacy
+/// one.
+pub fn has_sections(s: &str) -> bool {
+    s.lines().any(|l| section_name(l) == Some("lower"))
+}
+
+// Name of the section started by `line`, if any. Rows of keys may start with
+// `[` and end with `]` too, but always contain whitespace.
+fn section_name(line: &str) -> Option<&str> {
+    let trimmed = split_comment(line).0.trim();
+    if trimmed.len() > 2 && !trimmed.contains(char::is_whitespace) {
+        trimmed.strip_prefix('[')?.strip_suffix(']')
+    } else {
+        None
+    }
+}
+
+fn store(
+    name: &str,
+    line: usize,
+    keys: [char; 36],
+    lower: &mut Option<[char; 36]>,
+    upper: &mut Option<[char; 36]>,
+) -> Result<(), ParseError> {
+    let target = if name == "lower" { lower } else { upper };
+    if target.is_some() {
+        return error(line, 1, format!("duplicate section '[{}]'", name));
+    }
+    *target = Some(keys);
+    Ok(())
+}
+
+fn section_keys(
+    name: &str,
+    start: usize,
+    rows: &[(usize, Vec<(usize, char)>)],
+) -> Result<[char; 36], ParseError> {
+    if rows.len() < ROW_LENS.len() {
+        let line = rows.last().map_or(start, |(n, _)| *n);
+        return error(
+            line,
+            1,
+            format!("expected {} rows in '[{}]', found {}", ROW_LENS.len(), name, rows.len()),
+        );
+    }
+    if let Some((n, row)) = rows.get(ROW_LENS.len()) {
+        return error(*n, row[0].0, format!("too many rows in '[{}]'", name));
+    }
+
+    let mut keys = ['\0'; 36];
+    let mut pos = 0;
+    for (r, ((n, row), len)) in rows.iter().zip(ROW_LENS.iter()).enumerate() {
+        if row.len() != *len {
+            let column = match row.get(*len) {
+                Some((column, _)) => *column,
+                None => row.last().map_or(1, |(column, _)| column + 1),
+            };
+            return error(
+                *n,
+                column,
+                format!("expected {} keys in row {} of '[{}]', found {}", len, r + 1, name, row.len()),
+            );
+        }
+        for (_, key) in row {
+            keys[pos] = *key;
+            pos += 1;
+        }
+    }
+    Ok(keys)
+}
+
+// Keys of a line with the column they start at.
+fn tokenize(line: &str, n: usize) -> Result<Vec<(usize, char)>, ParseError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && !chars[i].is_whitespace() {
+            i += 1;
+        }
+        let token: String = chars[start..i].iter().collect();
+        match unescape(&token) {
+            Some(key) => tokens.push((start + 1, key)),
+            None => return error(n, start + 1, format!("invalid key '{}'", token)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads a single key as written in layout files, e.g. `a` or `\s`.
+pub fn unescape(token: &str) -> Option<char> {
+    let mut chars = token.chars();
+    match (chars.next()?, chars.next(), chars.as_str()) {
+        (c, None, _) => Some(c),
+        ('\\', Some(e), "") => match e {
+            's' => Some(' '),
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '0' => Some('\0'),
+            '#' | '\\' => Some(e),
+            _ => None,
+        },
+        ('\\', Some('u'), rest) => {
+            let hex = rest.strip_prefix('{')?.strip_suffix('}')?;
+            u32::from_str_radix(hex, 16).ok().and_then(std::char::from_u32)
+        }
+        _ => None,
+    }
+}
+
+fn escape(c: char) -> String {
+    match c {
+        ' ' => "\\s".to_string(),
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '\0' => "\\0".to_string(),
+        '\\' => "\\\\".to_string(),
+        _ if c.is_whitespace() || c.is_control() => format!("\\u{{{:x}}}", c as u32),
+        _ => c.to_string(),
+    }
+}
+
+// Splits a header line into its content and comment. A comment starts with a
+// `#` at the start of the line or after whitespace.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut prev = None;
+    for (i, c) in line.char_indices() {
+        if c == '#' && prev.is_none_or(char::is_whitespace) {
+            return (&line[..i], Some(&line[i + 1..]));
+        }
+        prev = Some(c);
+    }
+    (line, None)
+}
+
+// Escapes every `#` of a header value that would start a comment.
+fn escape_value(value: &str) -> String {
+    let mut out = String::new();
+    let mut prev = None;
+    for c in value.chars() {
+        if c == '#' && prev.is_none_or(char::is_whitespace) {
+            out.push('\\');
+        }
+        out.push(c);
+        prev = Some(c);
+    }
+    out
+}
+
+// Column at which `part`, a subslice of `line`, starts.
+fn column_of(line: &str, part: &str) -> usize {
+    let offset = part.as_ptr() as usize - line.as_ptr() as usize;
+    line[..offset].chars().count() + 1
+}
+
+/// Formats a layout as a layout file. `parse` reads it back unchanged.
+pub fn format(layout: &Layout, info: &LayoutInfo) -> String {
+    let mut out = String::new();
+    for comment in &info.comments {
+        out.push_str(&format!("#{}\n", comment));
+    }
+    let fields = [("name", &info.name), ("author", &info.author), ("geometry", &info.geometry)];
+    for (key, value) in fields.iter() {
+        if let Some(value) = value {
+            out.push_str(&format!("{}: {}\n", key, escape_value(value)));
+        }
+    }
+    if !info.pinned.is_empty() {
+        let pinned: Vec<String> = info.pinned.iter().map(|p| p.to_string()).collect();
+        out.push_str(&format!("pinned: {}\n", pinned.join(" ")));
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    for (name, keys) in [("lower", layout.lower()), ("upper", layout.upper())].iter() {
+        if *name == "upper" {
+            out.push('\n');
+        }
+        out.push_str(&format!("[{}]\n", name));
+        let mut pos = 0;
+        for (r, len) in ROW_LENS.iter().enumerate() {
+            let row: Vec<String> = keys[pos..pos + len].iter().map(|c| escape(*c)).collect();
+            pos += len;
+            let line = if r < 3 {
+                format!("{}  {}", row[..5].join(" "), row[5..].join(" "))
+            } else {
+                format!("        {}", row.join(" "))
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// Reads the fixed-width format of older versions of keygen: per layer, three
+// rows of five keys, a separator and the keys of the right hand, followed by
+// one or two lines with the thumb keys at columns 5 and 7.
+fn parse_legacy(s: &str) -> Option<Layout> {
+    let lines: Vec<Vec<char>> = s
+        .lines()
+        .map(|l| l.trim_end_matches('\r').chars().collect())
+        .collect();
+    let per_layer = match lines.len() {
+        8 => 4,
+        10 => 5,
+        _ => return None,
+    };
+
+    let mut layers = [['*'; 36], ['*'; 36]];
+    for (layer, lines) in layers.iter_mut().zip(lines.chunks(per_layer)) {
+        for (r, (start, right)) in [(0, 6), (11, 6), (22, 5)].iter().enumerate() {
+            let line = &lines[r];
+            if line.len() < 11 || line.len() > 6 + right {
+                return None;
+            }
+            layer[*start..start + 5].copy_from_slice(&line[..5]);
+            for (i, c) in line[6..].iter().enumerate() {
+                layer[start + 5 + i] = *c;
+            }
+        }
+        for (t, line) in lines[3..].iter().enumerate() {
+            for (i, column) in [4, 6].iter().enumerate() {
+                if let Some(c) = line.get(*column) {
+                    layer[32 + 2 * t + i] = *c;
+                }
+            }
+        }
+    }
+    Some(Layout::from_layers(layers[0], layers[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "# Shared on the forum\nname: C# layout # not part of the name\nauthor: \\#1 fan\npinned: 32 33\n\n[lower]\nj c y f k  z l , u q #\nr s t h d  m n a i o '\n/ v g p b  x w . ; -\n        e \\s\n        * *\n";
+
+    // Parses `s`, formats the result and checks that it parses to the same
+    // layout and metadata, and formats to the same text again.
+    fn round_trip(s: &str) -> (Layout, LayoutInfo) {
+        let (layout, info) = parse(s).unwrap();
+        let formatted = format(&layout, &info);
+        let (again, again_info) = parse(&formatted).unwrap();
+        assert_eq!(again, layout);
+        assert_eq!(again_info, info);
+        assert_eq!(format(&again, &again_info), formatted);
+        (layout, info)
+    }
+
+    #[test]
+    fn header_comments() {
+        let (layout, info) = round_trip(FILE);
+        assert_eq!(info.name.as_deref(), Some("C# layout"));
+        assert_eq!(info.author.as_deref(), Some("#1 fan"));
+        assert_eq!(info.pinned, vec![32, 33]);
+        assert_eq!(info.comments, vec![" Shared on the forum", " not part of the name"]);
+        assert_eq!(layout.lower()[10], '#');
+        assert_eq!(layout.upper()[10], '#');
+    }
+
+    #[test]
+    fn formatting_doesnt_matter() {
+        let expected = round_trip(FILE);
+        let (header, sections) = FILE.split_at(FILE.find("[lower]").unwrap());
+        let crlf = FILE.replace('\n', "\r\n");
+        let tabs = format!("{}{}", header, sections.replace(' ', "\t"));
+        // Single spaces between keys and no leading or trailing whitespace.
+        let compact: String = sections
+            .lines()
+            .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" ") + "\n")
+            .collect();
+        let compact = format!("{}{}", header, compact.trim_end_matches('\n'));
+        for s in &[crlf, tabs, compact] {
+            assert_eq!(round_trip(s), expected, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn format_round_trips_reference_layouts() {
+        for layout in [&crate::layout::QWERTY_LAYOUT, crate::penalty::INIT_LAYOUT] {
+            let s = format(layout, &LayoutInfo::default());
+            assert_eq!(&round_trip(&s).0, layout);
+        }
+    }
+
+    #[test]
+    fn repository_layout_file() {
+        let (layout, info) = round_trip(include_str!("../mroi.layout"));
+        assert_eq!(info.name.as_deref(), Some("mroi"));
+        assert_eq!(layout.lower()[0], 'v');
+    }
+}
@@ -1,8 +1,23 @@
 mod annealing;
 mod app;
+mod checkpoint;
+mod export;
+mod import;
+mod ingest;
+mod keylog;
 mod layout;
+mod layout_file;
+mod ngrams;
 mod penalty;
+mod preprocess;
+mod progress;
+mod registry;
+mod report;
+mod sampling;
 mod simulator;
+mod stats;
+mod svg;
+mod validate;
 
 use app::{Command, Config};
 use penalty::Corpus;
@@ -19,16 +34,69 @@ fn main() {
 
 fn run() -> Result<()> {
     let config = Config::from_env()?;
+    // Only `run` and `refine` stop gracefully; other commands quit right away.
+    if let Command::Run | Command::Refine = config.command {
+        checkpoint::install_interrupt_handler()?;
+    }
 
-    let buffer = std::fs::read_to_string(&config.corpus_path)?;
+    if let Command::ListLayouts = config.command {
+        registry::list(&config.registry);
+        return Ok(());
+    }
 
-    let corpus = Corpus::from(&buffer[..]);
+    let validating = matches!(config.command, Command::Validate);
+    // Issues that `validate` reports are warnings for commands using the layout.
+    let warn = !matches!(config.command, Command::Validate | Command::CorpusBuild | Command::CorpusStats);
+    if warn {
+        validate::warn(&validate::check(&config.layout));
+    }
+
+    if let Some(options) = &config.export {
+        return export::run(&config, options);
+    }
+    if validating && config.corpora.is_empty() {
+        return validate::run(&config.layout, None);
+    }
+
+    if config.corpora.is_empty() {
+        return Err("A corpus is required for this command".into());
+    }
+    if let Command::CorpusStats = config.command {
+        return stats::run(&config);
+    }
+    let corpora = config
+        .corpora
+        .iter()
+        .map(|(path, weight)| {
+            let corpus = ngrams::read_corpus(path, config.parallel, &config.preprocess, &config.sampling, config.context)?;
+            Ok((path.display().to_string(), *weight, corpus))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let corpus = Corpus::weighted(corpora);
+    if let Command::CorpusBuild = config.command {
+        return ngrams::build(&corpus, &config);
+    }
+    if let Some(held_out) = &corpus.held_out {
+        if held_out.len == 0.0 {
+            return Err("The held-out part of the corpus is empty; hold out a larger fraction".into());
+        }
+        if corpus.len == 0.0 {
+            return Err("Nothing of the corpus is left to optimise on; hold out a smaller fraction".into());
+        }
+    }
+    if warn {
+        validate::warn_corpus(&config.layout, &corpus);
+    }
 
     match config.command {
         Command::Run => simulator::run(&corpus, &config)?,
         Command::RunRefs => simulator::run_refs(&corpus, &config)?,
         Command::Refine => simulator::refine(&corpus, &config)?,
-        Command::Analyze => simulator::analyze(&corpus)?,
+        Command::Analyze => simulator::analyze(&corpus, &config.inputs)?,
+        Command::Validate => validate::run(&config.layout, Some(&corpus))?,
+        Command::Export | Command::ListLayouts | Command::CorpusBuild | Command::CorpusStats => {
+            unreachable!()
+        }
     };
     Ok(())
 }
@@ -0,0 +1,301 @@
+/// N-gram frequency tables, which can be used as a corpus instead of text and
+/// are written by `corpus build`.
+///
+/// Tables are either TSV, with an n-gram and its count on every line, or JSON,
+/// as an object mapping n-grams to counts or an array of `[ngram, count]`
+/// pairs. Every n-gram counts for the key it ends with: longer n-grams than
+/// the context are cut to their last keys, and shorter ones are scored without
+/// the penalties that look further back, like the first keys of a run of text.
+/// N-grams need at least the four keys that every penalty sees.
+use std::io::Write;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::app::Config;
+use crate::ingest;
+use crate::penalty::{Corpus, NGram, DEFAULT_CONTEXT};
+use crate::preprocess::Preprocess;
+use crate::sampling::Sampling;
+use crate::Result;
+
+/// Reads a corpus. Files ending in `.tsv` or `.json` are n-gram tables, which
+/// are used as they are; other files and directories are streamed as text and
+/// preprocessed, see `ingest`. Only text can be sampled, as tables have no
+/// lines.
+pub fn read_corpus(
+    path: &Path,
+    parallel: bool,
+    preprocess: &Preprocess,
+    sampling: &Sampling,
+    context: usize,
+) -> Result<Corpus> {
+    let table = |json| -> Result<Corpus> {
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read corpus {}: {}", path.display(), e))?;
+        parse(&s, json, context).map_err(|e| format!("Invalid n-gram table {}: {}", path.display(), e).into())
+    };
+    match extension(path).as_deref() {
+        _ if !is_table(path) => ingest::read_path(path, parallel, preprocess, sampling, context),
+        _ if sampling.is_active() => Err(format!(
+            "Can't hold out or bootstrap n-gram table {}, only text corpora",
+            path.display()
+        )
+        .into()),
+        Some("json") => table(true),
+        _ => table(false),
+    }
+}
+
+pub fn is_table(path: &Path) -> bool {
+    !path.is_dir() && matches!(extension(path).as_deref(), Some("tsv") | Some("json"))
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase)
+}
+
+/// Reads a table, as TSV or, if `json` is set, as JSON, into n-grams of
+/// at most `context` characters.
+pub fn parse(s: &str, json: bool, context: usize) -> Result<Corpus> {
+    let entries = if json { parse_json(s)? } else { parse_tsv(s)? };
+    let mut ngrams = Vec::new();
+    for (ngram, count) in entries {
+        let chars: Vec<char> = ngram.chars().collect();
+        if chars.len() < DEFAULT_CONTEXT {
+            return Err(format!(
+                "n-gram '{}' is shorter than the {} keys every penalty sees",
+                escape(&ngram),
+                DEFAULT_CONTEXT
+            )
+            .into());
+        }
+        if let Some(ngram) = NGram::new(&chars[chars.len().saturating_sub(context)..]) {
+            ngrams.push((ngram, count));
+        }
+    }
+    let mut corpus = Corpus::from_counts(ngrams);
+    // Tables only have typable n-grams; every character of the text ends one
+    // of them, except for the first few of every run.
+    for (ngram, count) in &corpus.ngrams {
+        *corpus.chars.entry(ngram.chars().last().unwrap()).or_insert(0.0) += count;
+    }
+    Ok(corpus)
+}
+
+// Lines of an n-gram, a tab and a count. N-grams escape tabs, newlines and
+// backslashes as `\t`, `\n` and `\\`. Empty lines and lines starting with `#`
+// without a tab are skipped.
+fn parse_tsv(s: &str) -> Result<Vec<(String, f64)>> {
+    let mut entries = Vec::new();
+    for (n, line) in s.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let (ngram, count) = match line.rsplit_once('\t') {
+            Some(fields) => fields,
+            None if line.is_empty() || line.starts_with('#') => continue,
+            None => return Err(format!("line {}: expected an n-gram and a count separated by a tab", n + 1).into()),
+        };
+        let ngram = unescape(ngram).ok_or_else(|| format!("line {}: invalid escape in '{}'", n + 1, ngram))?;
+        entries.push((ngram, parse_count(count.trim()).map_err(|e| format!("line {}: {}", n + 1, e))?));
+    }
+    Ok(entries)
+}
+
+fn parse_json(s: &str) -> Result<Vec<(String, f64)>> {
+    let value: Value = serde_json::from_str(s).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let count = |ngram: &str, count: &Value| match count.as_f64() {
+        Some(count) if count >= 0.0 => Ok((ngram.to_string(), count)),
+        _ => Err(format!("invalid count for '{}'", ngram)),
+    };
+    let entries = match &value {
+        Value::Object(ngrams) => ngrams.iter().map(|(ngram, n)| count(ngram, n)).collect(),
+        Value::Array(pairs) => pairs
+            .iter()
+            .map(|pair| match pair.as_array().map(Vec::as_slice) {
+                Some([Value::String(ngram), n]) => count(ngram, n),
+                _ => Err("expected [ngram, count] pairs".to_string()),
+            })
+            .collect(),
+        _ => Err("expected an object or an array of [ngram, count] pairs".to_string()),
+    };
+    Ok(entries?)
+}
+
+fn parse_count(s: &str) -> std::result::Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(count) if count.is_finite() && count >= 0.0 => Ok(count),
+        _ => Err(format!("invalid count '{}'", s)),
+    }
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next()? {
+                't' => '\t',
+                'n' => '\n',
+                'r' => '\r',
+                '\\' => '\\',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(out)
+}
+
+fn escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\t' => "\\t".to_string(),
+            '\n' => "\\n".to_string(),
+            '\r' => "\\r".to_string(),
+            '\\' => "\\\\".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Writes the n-gram counts of a corpus as a table, most frequent first.
+pub fn format(corpus: &Corpus, json: bool) -> String {
+    let mut counts: Vec<(&str, f64)> = corpus.ngrams.iter().map(|(q, count)| (q.as_str(), *count)).collect();
+    counts.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(b.0)));
+
+    if json {
+        // One pair per line, which keeps large tables readable.
+        let pairs: Vec<String> = counts
+            .iter()
+            .map(|(q, count)| serde_json::json!([q, json_count(*count)]).to_string())
+            .collect();
+        format!("[\n  {}\n]\n", pairs.join(",\n  "))
+    } else {
+        let mut out = String::from("# keygen n-gram counts\n");
+        for (ngram, count) in counts {
+            out.push_str(&format!("{}\t{}\n", escape(ngram), count));
+        }
+        out
+    }
+}
+
+/// The `corpus build` command: writes the corpus as a table to the output
+/// file, as JSON if it ends in `.json` and as TSV otherwise, or as TSV to
+/// stdout.
+pub fn build(corpus: &Corpus, config: &Config) -> Result<()> {
+    let json = config.output.as_deref().and_then(extension).as_deref() == Some("json");
+    let output = format(corpus, json);
+    match &config.output {
+        Some(path) => {
+            std::fs::write(path, output)?;
+            eprintln!("Corpus written to {}", path.display());
+        }
+        None => std::io::stdout().write_all(output.as_bytes())?,
+    }
+    Ok(())
+}
+
+// Whole counts are written as integers.
+fn json_count(count: f64) -> Value {
+    if count.fract() == 0.0 && count < u64::MAX as f64 {
+        Value::from(count as u64)
+    } else {
+        Value::from(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // N-grams of a corpus with their counts, sorted.
+    fn counts(corpus: &Corpus) -> Vec<(String, f64)> {
+        let mut counts: Vec<_> = corpus.ngrams.iter().map(|(n, c)| (n.as_str().to_string(), *c)).collect();
+        counts.sort_by(|a, b| a.0.cmp(&b.0));
+        counts
+    }
+
+    fn error(s: &str, json: bool, context: usize) -> String {
+        match parse(s, json, context) {
+            Ok(_) => panic!("{:?} was read as a table", s),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn expected(counts: &[(&str, f64)]) -> Vec<(String, f64)> {
+        counts.iter().map(|(n, c)| (n.to_string(), *c)).collect()
+    }
+
+    #[test]
+    fn tsv() {
+        let s = "# comment\r\nthe \t10\r\n\r\nand\\\\\t2.5\r\n of \t 3 \r\n";
+        let corpus = parse(s, false, 4).unwrap();
+        assert_eq!(counts(&corpus), expected(&[(" of ", 3.0), ("and\\", 2.5), ("the ", 10.0)]));
+        assert_eq!(corpus.len, 15.5);
+    }
+
+    #[test]
+    fn tsv_escapes() {
+        let entries = parse_tsv("a\\tb\\nc\\\\\t1\n").unwrap();
+        assert_eq!(entries, vec![("a\tb\nc\\".to_string(), 1.0)]);
+        for s in &["abcd\\x\t1\n", "abcd\\\t1\n"] {
+            assert!(error(s, false, 4).contains("invalid escape"), "{:?}", s);
+        }
+        assert_eq!(unescape(&escape("\t\n\r\\ x")).as_deref(), Some("\t\n\r\\ x"));
+    }
+
+    #[test]
+    fn json_object_and_pairs() {
+        let object = parse(r#"{"the ": 10, "and ": 2.5}"#, true, 4).unwrap();
+        let pairs = parse(r#"[["the ", 10], ["and ", 2.5]]"#, true, 4).unwrap();
+        assert_eq!(counts(&object), expected(&[("and ", 2.5), ("the ", 10.0)]));
+        assert_eq!(counts(&pairs), counts(&object));
+    }
+
+    #[test]
+    fn invalid_counts() {
+        for s in &["abcd\t-1\n", "abcd\tNaN\n", "abcd\tinf\n", "abcd\tmany\n", "abcd 1\n"] {
+            assert!(parse(s, false, 4).is_err(), "{:?}", s);
+        }
+        for s in &[r#"{"abcd": -1}"#, r#"{"abcd": "1"}"#, r#"[["abcd"]]"#, r#"[["abcd", 1, 2]]"#, "3", "{"] {
+            assert!(parse(s, true, 4).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn ngram_lengths() {
+        // Longer n-grams count once, for their last keys.
+        let corpus = parse("abcdef\t2\nabcd\t1\n", false, 4).unwrap();
+        assert_eq!(counts(&corpus), expected(&[("abcd", 1.0), ("cdef", 2.0)]));
+        // Shorter ones are kept as they are.
+        let corpus = parse("abcdef\t2\nabcd\t1\n", false, 5).unwrap();
+        assert_eq!(counts(&corpus), expected(&[("abcd", 1.0), ("bcdef", 2.0)]));
+        assert!(error("th\t5\n", false, 4).contains("shorter than the 4 keys"));
+    }
+
+    #[test]
+    fn untypable_ngrams_are_dropped() {
+        let corpus = parse("the \t1\n1234\t2\nnaïve\t3\n", false, 4).unwrap();
+        assert_eq!(counts(&corpus), expected(&[("the ", 1.0)]));
+    }
+
+    #[test]
+    fn build_and_read_round_trip() {
+        let dir = std::env::temp_dir().join(format!("keygen-ngrams-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let text = dir.join("text.txt");
+        std::fs::write(&text, "The quick brown fox jumps over the lazy dog.\nPack my box with five dozen jugs.\n").unwrap();
+        let (preprocess, sampling) = (Preprocess::default(), Sampling::default());
+        for context in [4, 6] {
+            let corpus = read_corpus(&text, false, &preprocess, &sampling, context).unwrap();
+            for (name, json) in [("table.tsv", false), ("table.json", true)] {
+                let table = dir.join(name);
+                std::fs::write(&table, format(&corpus, json)).unwrap();
+                let read = read_corpus(&table, false, &preprocess, &sampling, context).unwrap();
+                assert_eq!(counts(&read), counts(&corpus), "{} at context {}", name, context);
+                assert_eq!(read.len, corpus.len);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -69,6 +69,10 @@ const SAME_HAND_PENALTY: Option<f64> = Some(0.5);
 // Penalise 0.5 points for alternating hands three times in a row.
 const ALTERNATING_HAND_PENALTY: Option<f64> = Some(0.5);
 //
+// Penalise 0.5 points for using the same hand five times in a row, on top
+// of the same hand penalty. Only applies with a context of five or more keys.
+const SAME_HAND_STREAK_PENALTY: Option<f64> = Some(0.5);
+//
 // Penalise 0.125 points for rolling outwards.
 const ROLL_OUT_PENALTY: Option<f64> = Some(0.125);
 //
@@ -106,15 +110,21 @@ pub const LAYOUT_MASK: KeyMap<bool> = KeyMap([
 use rayon::prelude::*;
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::ops::Range;
 /// Methods for calculating the penalty of a keyboard layout given an input
 /// corpus string.
 use std::vec::Vec;
-use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 use crate::layout::{self, Finger, Hand, KeyMap, KeyPress, Layout, LayoutPosMap, Row};
+use crate::sampling::{self, Bootstrap};
+
+/// Default number of keys that penalties see: the current one and those
+/// before it.
+pub const DEFAULT_CONTEXT: usize = 4;
+/// Most keys that penalties can see.
+pub const MAX_CONTEXT: usize = 8;
 
+// The last four key presses of a window, which most penalties look at.
 pub struct KeyPressQuartad<'a> {
     curr: &'a KeyPress,
     old1: &'a KeyPress,
@@ -122,50 +132,200 @@ pub struct KeyPressQuartad<'a> {
     old3: &'a KeyPress,
 }
 
-#[derive(PartialEq, Eq, Debug, Hash)]
-pub struct Quartad<'a>(&'a str);
-impl Quartad<'_> {
-    pub fn get_kp_quartad<'a>(&self, pos_map: &'a LayoutPosMap) -> Option<KeyPressQuartad<'a>> {
-        let mut chars = self.0.chars().rev().map(|c| pos_map.get_key_position(c));
-        Some(KeyPressQuartad {
-            curr: chars.next().flatten()?,
-            old1: chars.next().flatten()?,
-            old2: chars.next().flatten()?,
-            old3: chars.next().flatten()?,
-        })
+// Consecutive key presses, as many as the context.
+pub struct KeyPressWindow<'a> {
+    // The current key press first, followed by those before it; `len` of
+    // them are valid.
+    keys: [&'a KeyPress; MAX_CONTEXT],
+    len: usize,
+}
+
+impl<'a> KeyPressWindow<'a> {
+    // The key press `n` presses before the current one, if the window reaches
+    // that far back.
+    fn old(&self, n: usize) -> Option<&'a KeyPress> {
+        if n < self.len {
+            Some(self.keys[n])
+        } else {
+            None
+        }
+    }
+
+    fn quartad(&self) -> KeyPressQuartad<'a> {
+        KeyPressQuartad {
+            curr: self.keys[0],
+            old1: self.keys[1],
+            old2: self.keys[2],
+            old3: self.keys[3],
+        }
     }
 }
 
-pub struct Corpus<'a> {
-    pub quartads: HashMap<Quartad<'a>, usize>,
-    pub len: usize,
+// Consecutive characters of a corpus, as many as the context. Layouts only
+// map ASCII characters to keys, so a byte per character suffices.
+#[derive(PartialEq, Eq, Debug, Hash, Clone, Copy, PartialOrd, Ord)]
+pub struct NGram {
+    len: u8,
+    bytes: [u8; MAX_CONTEXT],
 }
-impl<'a> From<&'a str> for Corpus<'a> {
-    fn from(string: &'a str) -> Self {
-        let mut range: Range<usize> = 0..0;
-        let mut quartads: HashMap<Quartad<'a>, usize> = HashMap::new();
+impl NGram {
+    /// Returns `None` if any of the characters isn't ASCII or there are more
+    /// than `MAX_CONTEXT`.
+    pub fn new(chars: &[char]) -> Option<NGram> {
+        if chars.len() > MAX_CONTEXT {
+            return None;
+        }
+        let mut bytes = [0; MAX_CONTEXT];
+        for (byte, c) in bytes.iter_mut().zip(chars.iter()) {
+            if !c.is_ascii() {
+                return None;
+            }
+            *byte = *c as u8;
+        }
+        Some(NGram { len: chars.len() as u8, bytes })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn chars(&self) -> impl DoubleEndedIterator<Item = char> + '_ {
+        self.bytes[..self.len()].iter().map(|&b| b as char)
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.len()]).unwrap()
+    }
+
+    // N-grams shorter than four keys have no window, as every penalty sees
+    // at least four.
+    fn get_kp_window<'a>(&self, pos_map: &'a LayoutPosMap) -> Option<KeyPressWindow<'a>> {
+        if self.len() < DEFAULT_CONTEXT {
+            return None;
+        }
+        let mut chars = self.chars().rev();
+        let curr = pos_map.get_key_position(chars.next()?)?;
+        let mut keys = [curr; MAX_CONTEXT];
+        for (key, c) in keys[1..].iter_mut().zip(chars) {
+            *key = pos_map.get_key_position(c)?;
+        }
+        Some(KeyPressWindow { keys, len: self.len() })
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Corpus {
+    // Number of occurrences of every n-gram, which are as long as the
+    // context. Counts are fractional once corpora are combined with weights.
+    pub ngrams: HashMap<NGram, f64>,
+    pub len: f64,
+    // Number of occurrences of every character of the text, including those
+    // that the default layout can't type.
+    pub chars: HashMap<char, f64>,
+    // The weighted corpora this one is combined from, if there are several.
+    pub parts: Vec<CorpusPart>,
+    // The part of the text held out from optimisation, and the corpus divided
+    // into blocks for bootstrapping, if requested; see `sampling`.
+    pub held_out: Option<Box<Corpus>>,
+    pub bootstrap: Option<Bootstrap>,
+}
+
+#[derive(Clone)]
+pub struct CorpusPart {
+    pub name: String,
+    // Share of the combined corpus, i.e. the weights normalised to sum to 1.
+    pub share: f64,
+    pub corpus: Corpus,
+}
+
+impl Corpus {
+    /// Builds a corpus from n-grams with their counts, e.g. from text or an
+    /// n-gram table. N-grams with characters that the default layout can't
+    /// type are dropped.
+    pub fn from_counts<I>(counts: I) -> Corpus
+    where
+        I: IntoIterator<Item = (NGram, f64)>,
+    {
         let position_map = INIT_LAYOUT.get_position_map();
+        let mut corpus = Corpus::default();
+        for (ngram, count) in counts {
+            if ngram.chars().all(|c| position_map.get_key_position(c).is_some()) {
+                *corpus.ngrams.entry(ngram).or_insert(0.0) += count;
+                corpus.len += count;
+            }
+        }
+        corpus
+    }
+
+    /// Adds the counts of another corpus to this one.
+    pub fn merge(&mut self, other: &Corpus) {
+        self.add(other, 1.0);
+    }
+
+    /// Adds the counts of another corpus, including its held-out part and
+    /// bootstrap blocks, multiplied by `scale`.
+    pub fn add(&mut self, other: &Corpus, scale: f64) {
+        for (ngram, count) in &other.ngrams {
+            *self.ngrams.entry(*ngram).or_insert(0.0) += count * scale;
+        }
+        self.len += other.len * scale;
+        add_chars(&mut self.chars, &other.chars, scale);
+        if let Some(held_out) = &other.held_out {
+            self.held_out.get_or_insert_with(Box::default).add(held_out, scale);
+        }
+        if let Some(bootstrap) = &other.bootstrap {
+            self.bootstrap
+                .get_or_insert_with(|| Bootstrap::new(bootstrap.resamples))
+                .add(bootstrap, scale);
+        }
+    }
 
-        for (i, c) in string.chars().enumerate() {
-            match position_map.get_key_position(c) {
-                Some(_) => {
-                    range.end = i + 1;
-                    if range.end - range.start >= 4 {
-                        range.start = range.end - 4;
-                        let quartad = Quartad(&string[range.clone()]);
-                        let entry = quartads.entry(quartad).or_insert(0);
-                        *entry += 1;
-                    };
+    /// Combines named corpora with the given weights. Every corpus contributes
+    /// its share of the weights to the n-gram frequencies regardless of its
+    /// size; the combined counts are scaled to the total size of all corpora,
+    /// so that a single corpus keeps its counts. Held-out parts are combined
+    /// the same way.
+    pub fn weighted(corpora: Vec<(String, f64, Corpus)>) -> Corpus {
+        let weights: f64 = corpora.iter().map(|(_, weight, _)| weight).sum();
+        let len: f64 = corpora.iter().map(|(_, _, corpus)| corpus.len).sum();
+        let mut ngrams: HashMap<NGram, f64> = HashMap::new();
+        let mut chars = HashMap::new();
+        let mut parts = Vec::new();
+        let mut held_out = Vec::new();
+        let mut bootstrap: Option<Bootstrap> = None;
+        for (name, weight, mut corpus) in corpora {
+            let share = weight / weights;
+            if corpus.len > 0.0 {
+                let scale = share * len / corpus.len;
+                for (ngram, count) in &corpus.ngrams {
+                    *ngrams.entry(*ngram).or_insert(0.0) += count * scale;
                 }
-                None => {
-                    range = (i + 1)..(i + 1);
+                add_chars(&mut chars, &corpus.chars, scale);
+                if let Some(blocks) = &corpus.bootstrap {
+                    bootstrap.get_or_insert_with(|| Bootstrap::new(blocks.resamples)).add(blocks, scale);
                 }
             }
+            if let Some(part) = corpus.held_out.take() {
+                held_out.push((name.clone(), weight, *part));
+            }
+            parts.push(CorpusPart { name, share, corpus });
         }
+        let len = ngrams.values().sum();
+        if parts.len() == 1 {
+            parts.clear();
+        }
+        let held_out = if held_out.is_empty() {
+            None
+        } else {
+            Some(Box::new(Corpus::weighted(held_out)))
+        };
+        Corpus { len, ngrams, chars, parts, held_out, bootstrap }
+    }
+}
 
-        let len = quartads.iter().map(|(_, count)| count).sum();
-
-        Corpus { len, quartads }
+fn add_chars(chars: &mut HashMap<char, f64>, other: &HashMap<char, f64>, scale: f64) {
+    for (c, count) in other {
+        *chars.entry(*c).or_insert(0.0) += count * scale;
     }
 }
 
@@ -174,15 +334,14 @@ impl Layout {
     pub fn penalize(&self, corpus: &Corpus) -> f64 {
         let pos_map = self.get_position_map();
         corpus
-            .quartads
+            .ngrams
             .iter()
-            .map(|(quartad, &count)| {
+            .filter_map(|(ngram, &count)| {
                 let mut total = TotalPenalty::new();
-                let kp_quartad = quartad.get_kp_quartad(&pos_map)?;
-                penalize_kp_quartad(&kp_quartad, &mut total);
-                Some(count as f64 * total.value)
+                let kp_window = ngram.get_kp_window(&pos_map)?;
+                penalize_kp_window(&kp_window, &mut total);
+                Some(count * total.value)
             })
-            .flatten()
             .sum()
     }
 
@@ -190,77 +349,124 @@ impl Layout {
     pub fn par_penalize(&self, corpus: &Corpus) -> f64 {
         let pos_map = self.get_position_map();
         corpus
-            .quartads
+            .ngrams
             .par_iter()
-            .map(|(quartad, &count)| {
+            .map(|(ngram, &count)| {
                 let mut total = TotalPenalty::new();
-                let kp_quartad = quartad.get_kp_quartad(&pos_map)?;
-                penalize_kp_quartad(&kp_quartad, &mut total);
-                Some(count as f64 * total.value)
+                let kp_window = ngram.get_kp_window(&pos_map)?;
+                penalize_kp_window(&kp_window, &mut total);
+                Some(count * total.value)
             })
             .flatten()
             .sum()
     }
 
-    pub fn penalize_with_details<'a>(&self, corpus: &'a Corpus) -> LayoutPenalty<'a> {
+    pub fn penalize_with_details(&self, corpus: &Corpus) -> LayoutPenalty {
         let pos_map = self.get_position_map();
 
         let mut total = 0.0;
-        let mut high_keys: HashMap<PenaltyVar, HashMap<&'a str, f64>> = HashMap::new();
+        let mut high_keys: HashMap<PenaltyVar, HashMap<&str, f64>> = HashMap::new();
         let mut usage = HashMap::new();
+        let mut key_usage = KeyMap([0.0; 36]);
+        let mut key_penalty = KeyMap([0.0; 36]);
 
         corpus
-            .quartads
+            .ngrams
             .iter()
-            .map(|(quartad, &count)| {
-                let kc_quartad = quartad.get_kp_quartad(&pos_map)?;
-                let mut details = DetailedPenalty::new(&quartad, count);
+            .filter_map(|(ngram, &count)| {
+                let kp_window = ngram.get_kp_window(&pos_map)?;
+                let mut details = DetailedPenalty::new(ngram, count);
 
-                penalize_kp_quartad(&kc_quartad, &mut details);
+                penalize_kp_window(&kp_window, &mut details);
                 Some(details)
             })
-            .flatten()
             .for_each(|details| {
+                // Penalties are attributed to the key that completes the n-gram.
+                let last = details.ngram.chars().last().unwrap();
+                let last_pos = pos_map.get_key_position(last).unwrap().pos;
                 details.value.iter().for_each(|(pen, (s, v))| {
-                    let pen_high_keys = high_keys.entry(*pen).or_insert(HashMap::new());
+                    let pen_high_keys = high_keys.entry(*pen).or_default();
                     let entry = pen_high_keys.entry(s).or_insert(0.0);
                     *entry += v;
                     total += v;
-
+                    key_penalty.0[last_pos] += v;
                 });
-                let c = details.quartad.0.chars().next().unwrap();
-                let KeyPress { finger, hand, .. } = pos_map.get_key_position(c).unwrap();
+                // Usage is counted for the first of the last four keys, which
+                // are all the n-gram has at the default context, so that it
+                // doesn't depend on the context.
+                let c = details.ngram.chars().rev().nth(DEFAULT_CONTEXT - 1).unwrap();
+                let KeyPress { finger, hand, pos, .. } = pos_map.get_key_position(c).unwrap();
                 let finger_usage = usage
                     .entry(*hand)
                     .or_insert(HashMap::new())
                     .entry(*finger)
                     .or_insert(0.0);
-                *finger_usage += details.count as f64/corpus.len as f64
+                *finger_usage += details.count / corpus.len;
+                key_usage.0[*pos] += details.count / corpus.len;
             });
 
         LayoutPenalty {
             usage,
+            key_usage,
+            key_penalty,
             total,
-            high_keys,
-            scaled: total / corpus.len as f64,
+            high_keys: high_keys
+                .into_iter()
+                .map(|(var, keys)| (var, keys.into_iter().map(|(s, v)| (s.to_string(), v)).collect()))
+                .collect(),
+            scaled: total / corpus.len,
+            parts: corpus
+                .parts
+                .iter()
+                .map(|part| (part.name.clone(), part.share, self.penalize(&part.corpus) / part.corpus.len))
+                .collect(),
+            resamples: corpus.bootstrap.as_ref().map(|b| b.scaled(self)).unwrap_or_default(),
+            held_out: corpus.held_out.as_ref().map(|held_out| {
+                (
+                    self.penalize(held_out) / held_out.len,
+                    held_out.bootstrap.as_ref().map(|b| b.scaled(self)).unwrap_or_default(),
+                )
+            }),
         }
     }
 }
 
-pub struct LayoutPenalty<'a> {
+pub struct LayoutPenalty {
     pub usage: HashMap<Hand, HashMap<Finger, f64>>,
+    pub key_usage: KeyMap<f64>,
+    pub key_penalty: KeyMap<f64>,
     pub total: f64,
     pub scaled: f64,
-    pub high_keys: HashMap<PenaltyVar, HashMap<&'a str, f64>>,
+    pub high_keys: HashMap<PenaltyVar, HashMap<String, f64>>,
+    // Name, share and scaled penalty of every corpus the corpus is combined
+    // from.
+    pub parts: Vec<(String, f64, f64)>,
+    // Scaled penalties on the bootstrap resamples of the corpus, and the
+    // scaled penalty on the held-out part with those on its resamples.
+    pub resamples: Vec<f64>,
+    pub held_out: Option<(f64, Vec<f64>)>,
 }
 
-impl Display for LayoutPenalty<'_> {
+impl Display for LayoutPenalty {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // Create a stack-allocated buffer...
         let total_int = self.total as i64;
         let mut buf = Buffer::default();
         buf.write_formatted(&total_int, &Locale::en);
         writeln!(f, "total: {}, scaled: {}", buf, self.scaled)?;
+        for (name, share, scaled) in &self.parts {
+            writeln!(f, "  {} ({:.0}%): scaled: {}", name, share * 100.0, scaled)?;
+        }
+        if let Some(interval) = sampling::interval(&self.resamples) {
+            writeln!(f, "  scaled 95% CI: {}", sampling::format_interval(interval))?;
+        }
+        if let Some((scaled, resamples)) = &self.held_out {
+            write!(f, "  held-out: scaled: {}", scaled)?;
+            if let Some(interval) = sampling::interval(resamples) {
+                write!(f, ", 95% CI: {}", sampling::format_interval(interval))?;
+            }
+            writeln!(f)?;
+        }
 
         // Hand::iter()
         //     .map(|hand| {
@@ -299,10 +505,11 @@ impl Display for LayoutPenalty<'_> {
     }
 }
 
-fn penalize_kp_quartad<T>(kp_quartad: &KeyPressQuartad, total_penalty: &mut T)
+fn penalize_kp_window<T>(kp_window: &KeyPressWindow, total_penalty: &mut T)
 where
     T: PenaltyAccumulator,
 {
+    let kp_quartad = &kp_window.quartad();
     let KeyPressQuartad {
         curr,
         old1,
@@ -310,34 +517,37 @@ where
         old3,
     } = kp_quartad;
 
-    total_penalty.add(penalties::base(&kp_quartad));
+    total_penalty.add(penalties::base(kp_quartad));
 
     if curr.hand == old1.hand {
-        total_penalty.add(penalties::same_finger(&kp_quartad));
-        total_penalty.add(penalties::long_jump(&kp_quartad));
-        total_penalty.add(penalties::long_jump_hand(&kp_quartad));
-        total_penalty.add(penalties::long_jump_consecutive(&kp_quartad));
-        total_penalty.add(penalties::pinky_ring(&kp_quartad));
-        total_penalty.add(penalties::pinky_ring_twist(&kp_quartad));
-        total_penalty.add(penalties::roll_out(&kp_quartad));
-        total_penalty.add(penalties::roll_in(&kp_quartad));
+        total_penalty.add(penalties::same_finger(kp_quartad));
+        total_penalty.add(penalties::long_jump(kp_quartad));
+        total_penalty.add(penalties::long_jump_hand(kp_quartad));
+        total_penalty.add(penalties::long_jump_consecutive(kp_quartad));
+        total_penalty.add(penalties::pinky_ring(kp_quartad));
+        total_penalty.add(penalties::pinky_ring_twist(kp_quartad));
+        total_penalty.add(penalties::roll_out(kp_quartad));
+        total_penalty.add(penalties::roll_in(kp_quartad));
     }
 
     if curr.hand == old1.hand && old1.hand == old2.hand {
-        total_penalty.add(penalties::ring_stretch(&kp_quartad));
-        total_penalty.add(penalties::roll_reversal(&kp_quartad));
-        total_penalty.add(penalties::twist(&kp_quartad));
+        total_penalty.add(penalties::ring_stretch(kp_quartad));
+        total_penalty.add(penalties::roll_reversal(kp_quartad));
+        total_penalty.add(penalties::twist(kp_quartad));
     }
 
     if curr.hand == old2.hand && curr.finger == old2.finger {
-        total_penalty.add(penalties::same_finger_sandwich(&kp_quartad));
-        total_penalty.add(penalties::long_jump_sandwich(&kp_quartad));
+        total_penalty.add(penalties::same_finger_sandwich(kp_quartad));
+        total_penalty.add(penalties::long_jump_sandwich(kp_quartad));
     }
 
     if curr.hand == old1.hand && old1.hand == old2.hand && old2.hand == old3.hand {
-        total_penalty.add(penalties::same_hand(&kp_quartad));
+        total_penalty.add(penalties::same_hand(kp_quartad));
+        if kp_window.old(4).map(|old4| old4.hand) == Some(curr.hand) {
+            total_penalty.add(penalties::same_hand_streak(kp_window));
+        }
     } else if curr.hand != old1.hand && old1.hand != old2.hand && old2.hand != old3.hand {
-        total_penalty.add(penalties::alternating_hand(&kp_quartad));
+        total_penalty.add(penalties::alternating_hand(kp_quartad));
     }
 }
 
@@ -359,28 +569,30 @@ pub enum PenaltyVar {
     LongJumpSandwich,
     SameHand,
     AlternatingHand,
+    SameHandStreak,
 }
 
 impl Display for PenaltyVar {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         use PenaltyVar::*;
         match self {
-            Base => write!(f, "{}", "Base"),
-            SameFinger => write!(f, "{}", "Same Finger"),
-            LongJump => write!(f, "{}", "Long Jump"),
-            LongJumpHand => write!(f, "{}", "Long Jump Hand"),
-            LongJumpConsecutive => write!(f, "{}", "Long Jump Consecutive"),
-            PinkyRing => write!(f, "{}", "Pinky Follows Ring"),
-            PinkyRingTwist => write!(f, "{}", "Pinky Ring Twist"),
-            RollOut => write!(f, "{}", "Roll Out"),
-            RollIn => write!(f, "{}", "Roll In"),
-            RingStretch => write!(f, "{}", "Ring Stretch"),
-            RollReversal => write!(f, "{}", "Roll Reversal"),
-            Twist => write!(f, "{}", "Twist"),
-            SameFingerSandwich => write!(f, "{}", "Same Finger Sandwich"),
-            LongJumpSandwich => write!(f, "{}", "Long Jump Sandwich"),
-            SameHand => write!(f, "{}", "Same Hand"),
-            AlternatingHand => write!(f, "{}", "Alternating Hand"),
+            Base => write!(f, "Base"),
+            SameFinger => write!(f, "Same Finger"),
+            LongJump => write!(f, "Long Jump"),
+            LongJumpHand => write!(f, "Long Jump Hand"),
+            LongJumpConsecutive => write!(f, "Long Jump Consecutive"),
+            PinkyRing => write!(f, "Pinky Follows Ring"),
+            PinkyRingTwist => write!(f, "Pinky Ring Twist"),
+            RollOut => write!(f, "Roll Out"),
+            RollIn => write!(f, "Roll In"),
+            RingStretch => write!(f, "Ring Stretch"),
+            RollReversal => write!(f, "Roll Reversal"),
+            Twist => write!(f, "Twist"),
+            SameFingerSandwich => write!(f, "Same Finger Sandwich"),
+            LongJumpSandwich => write!(f, "Long Jump Sandwich"),
+            SameHand => write!(f, "Same Hand"),
+            AlternatingHand => write!(f, "Alternating Hand"),
+            SameHandStreak => write!(f, "Same Hand Streak"),
         }
     }
 }
@@ -682,6 +894,16 @@ mod penalties {
             value: ALTERNATING_HAND_PENALTY,
         }
     }
+
+    #[inline(always)]
+    pub fn same_hand_streak(_kp_window: &KeyPressWindow) -> Penalty {
+        // Assumes curr.hand == old1.hand == old2.hand == old3.hand == old4.hand
+        Penalty {
+            kind: SameHandStreak,
+            relevant_keys: 5,
+            value: SAME_HAND_STREAK_PENALTY,
+        }
+    }
 }
 
 pub struct Penalty {
@@ -711,15 +933,15 @@ impl PenaltyAccumulator for TotalPenalty {
 }
 
 struct DetailedPenalty<'a> {
-    quartad: &'a Quartad<'a>,
-    count: usize,
+    ngram: &'a NGram,
+    count: f64,
     value: Vec<(PenaltyVar, (&'a str, f64))>,
 }
 impl<'a> DetailedPenalty<'a> {
-    pub fn new(quartad: &'a Quartad<'a>, count: usize) -> DetailedPenalty<'a> {
+    pub fn new(ngram: &'a NGram, count: f64) -> DetailedPenalty<'a> {
         DetailedPenalty {
             count,
-            quartad,
+            ngram,
             value: Vec::new(),
         }
     }
@@ -727,9 +949,10 @@ impl<'a> DetailedPenalty<'a> {
 
 impl<'a> PenaltyAccumulator for DetailedPenalty<'a> {
     fn add(&mut self, penalty: Penalty) -> Option<()> {
-        let s = &self.quartad.0[4 - penalty.relevant_keys..4];
+        let s = self.ngram.as_str();
+        let s = &s[s.len() - penalty.relevant_keys..];
         self.value
-            .push((penalty.kind, (s, self.count as f64 * penalty.value?)));
+            .push((penalty.kind, (s, self.count * penalty.value?)));
         Some(())
     }
 }
@@ -743,3 +966,75 @@ fn is_roll_out(curr: Finger, prev: Finger) -> bool {
 fn is_roll_in(curr: Finger, prev: Finger) -> bool {
     prev > curr
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ngrams;
+
+    // Corpus of an n-gram table whose characters are those of its n-grams.
+    fn corpus(table: &str) -> Corpus {
+        let mut corpus = ngrams::parse(table, false, DEFAULT_CONTEXT).unwrap();
+        for (ngram, count) in &corpus.ngrams {
+            for c in ngram.chars() {
+                *corpus.chars.entry(c).or_insert(0.0) += count;
+            }
+        }
+        corpus
+    }
+
+    fn count(corpus: &Corpus, ngram: &str) -> f64 {
+        let chars: Vec<char> = ngram.chars().collect();
+        corpus.ngrams[&NGram::new(&chars).unwrap()]
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn single_corpus_keeps_counts() {
+        let combined = Corpus::weighted(vec![("a".to_string(), 2.0, corpus("the \t30\nand \t10\n"))]);
+        assert_eq!(combined.len, 40.0);
+        assert_eq!(count(&combined, "the "), 30.0);
+        assert_eq!(count(&combined, "and "), 10.0);
+        assert_eq!(combined.chars[&'t'], 30.0);
+        assert!(combined.parts.is_empty());
+        assert!(combined.held_out.is_none());
+    }
+
+    #[test]
+    fn weights_are_shares_of_the_total() {
+        // The larger corpus gets a quarter of the frequencies.
+        let combined = Corpus::weighted(vec![
+            ("large".to_string(), 1.0, corpus("the \t30\n")),
+            ("small".to_string(), 3.0, corpus("and \t6\nthe \t4\n")),
+        ]);
+        assert_close(combined.len, 40.0);
+        assert_close(count(&combined, "the "), 10.0 + 12.0);
+        assert_close(count(&combined, "and "), 18.0);
+        assert_close(combined.chars[&'t'], 22.0);
+        assert_close(combined.chars[&'a'], 18.0);
+
+        let parts: Vec<(&str, f64, f64)> = combined.parts.iter().map(|p| (p.name.as_str(), p.share, p.corpus.len)).collect();
+        assert_eq!(parts, [("large", 0.25, 30.0), ("small", 0.75, 10.0)]);
+    }
+
+    #[test]
+    fn held_out_parts_are_weighted() {
+        let mut large = corpus("the \t30\n");
+        large.held_out = Some(Box::new(corpus("the \t3\n")));
+        let mut small = corpus("and \t10\n");
+        small.held_out = Some(Box::new(corpus("and \t1\n")));
+        let combined = Corpus::weighted(vec![("large".to_string(), 1.0, large), ("small".to_string(), 1.0, small)]);
+        assert_close(count(&combined, "the "), 20.0);
+        assert_close(count(&combined, "and "), 20.0);
+
+        let held_out = combined.held_out.as_ref().unwrap();
+        assert_close(held_out.len, 4.0);
+        assert_close(count(held_out, "the "), 2.0);
+        assert_close(count(held_out, "and "), 2.0);
+        assert_eq!(held_out.parts.len(), 2);
+        assert!(combined.parts.iter().all(|part| part.corpus.held_out.is_none()));
+    }
+}
@@ -0,0 +1,281 @@
+/// Preprocessing of text corpora before their n-grams are counted.
+use std::fmt;
+
+use regex::Regex;
+
+#[derive(Debug, Clone, Default)]
+pub struct Preprocess {
+    // Count uppercase letters as their lowercase letter.
+    pub fold_case: bool,
+    // Count runs of spaces as a single space.
+    pub collapse_whitespace: bool,
+    // Characters that newlines and tabs are typed as. Without one they are
+    // kept and break n-grams, like any character the layout can't type.
+    pub newline: Option<char>,
+    pub tab: Option<char>,
+    // Drop digits, which then break n-grams.
+    pub strip_digits: bool,
+    // Skip lines matching this expression entirely.
+    pub drop_lines: Option<Regex>,
+    pub code: Option<Code>,
+    // Weight the n-grams of keystroke logs by the latency of their last
+    // keystroke relative to the mean, so that slow transitions count more.
+    pub latency_weights: bool,
+}
+
+/// Reading source code rather than prose. Directories are walked respecting
+/// `.gitignore` and only files with one of `extensions` are read, and leading
+/// indentation is stripped from every line, as editors insert it.
+#[derive(Debug, Clone)]
+pub struct Code {
+    pub extensions: Vec<String>,
+    // Drop closing brackets and every second double quote of a line, which
+    // editors insert along with the opening one.
+    pub autopairs: bool,
+    // Weights of n-grams made of identifier characters (letters, digits,
+    // underscores and spaces) and of those with any other character.
+    pub identifier_weight: f64,
+    pub punctuation_weight: f64,
+}
+
+pub const DEFAULT_CODE_EXTENSIONS: &str = "rs,ts,tsx,js,jsx,py,go,c,h,cpp,hpp,java,kt,swift,rb,sh";
+
+impl Code {
+    pub fn includes(&self, path: &std::path::Path) -> bool {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) => self.extensions.iter().any(|e| e == extension),
+            None => false,
+        }
+    }
+}
+
+impl Preprocess {
+    /// Applies the character transformations to `line`, which may end in a
+    /// newline, appending the result to `out`. Stripped digits are written as
+    /// `\0`, which breaks n-grams. Returns `false` without writing anything if
+    /// the line is dropped. `space` tells whether the last character written
+    /// was a space and is updated.
+    pub fn line(&self, line: &str, out: &mut String, space: &mut bool) -> bool {
+        if let Some(regex) = &self.drop_lines {
+            if regex.is_match(line.trim_end_matches(&['\n', '\r'][..])) {
+                return false;
+            }
+        }
+        let line = match &self.code {
+            Some(_) => line.trim_start_matches(&[' ', '\t'][..]),
+            None => line,
+        };
+        let autopairs = self.code.as_ref().is_some_and(|code| code.autopairs);
+        let mut quotes = 0;
+        for c in line.chars() {
+            let c = match c {
+                ')' | ']' | '}' if autopairs => '\0',
+                '"' if autopairs => {
+                    quotes += 1;
+                    if quotes % 2 == 0 {
+                        '\0'
+                    } else {
+                        c
+                    }
+                }
+                '\n' => self.newline.unwrap_or('\n'),
+                '\r' if self.newline.is_some() => continue,
+                '\t' => self.tab.unwrap_or('\t'),
+                c if self.strip_digits && c.is_ascii_digit() => '\0',
+                c => c,
+            };
+            if c == ' ' && *space && self.collapse_whitespace {
+                continue;
+            }
+            *space = c == ' ';
+            if self.fold_case && c.is_uppercase() {
+                out.extend(c.to_lowercase());
+            } else {
+                out.push(c);
+            }
+        }
+        true
+    }
+
+    /// How much an n-gram counts.
+    pub fn weight(&self, ngram: &[char]) -> f64 {
+        match &self.code {
+            Some(code) if ngram.iter().all(|&c| c.is_alphanumeric() || c == '_' || c == ' ') => {
+                code.identifier_weight
+            }
+            Some(code) => code.punctuation_weight,
+            None => 1.0,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        !self.fold_case
+            && !self.collapse_whitespace
+            && self.newline.is_none()
+            && self.tab.is_none()
+            && !self.strip_digits
+            && self.drop_lines.is_none()
+            && self.code.is_none()
+            && !self.latency_weights
+    }
+}
+
+// A description of the enabled steps, as shown in reports.
+impl fmt::Display for Preprocess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut steps = Vec::new();
+        if self.fold_case {
+            steps.push("fold case".to_string());
+        }
+        if self.collapse_whitespace {
+            steps.push("collapse whitespace".to_string());
+        }
+        if let Some(c) = self.newline {
+            steps.push(format!("newline as {:?}", c));
+        }
+        if let Some(c) = self.tab {
+            steps.push(format!("tab as {:?}", c));
+        }
+        if self.strip_digits {
+            steps.push("strip digits".to_string());
+        }
+        if let Some(regex) = &self.drop_lines {
+            steps.push(format!("drop lines matching {}", regex));
+        }
+        if let Some(code) = &self.code {
+            steps.push(format!("code ({}), strip indentation", code.extensions.join(", ")));
+            if code.autopairs {
+                steps.push("drop autopaired characters".to_string());
+            }
+            if code.identifier_weight != 1.0 || code.punctuation_weight != 1.0 {
+                steps.push(format!(
+                    "weight identifiers {} and punctuation {}",
+                    code.identifier_weight, code.punctuation_weight
+                ));
+            }
+        }
+        if self.latency_weights {
+            steps.push("weight keystrokes by latency".to_string());
+        }
+        if steps.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", steps.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The lines run through `preprocess` one after the other, with dropped
+    // lines left out.
+    fn apply(preprocess: &Preprocess, lines: &[&str]) -> String {
+        let (mut out, mut space) = (String::new(), false);
+        for line in lines {
+            preprocess.line(line, &mut out, &mut space);
+        }
+        out
+    }
+
+    #[test]
+    fn identity() {
+        let preprocess = Preprocess::default();
+        assert!(preprocess.is_identity());
+        assert_eq!(preprocess.to_string(), "none");
+        let lines = ["Ünïcode  and\tTabs 42\r\n", "  next line\n"];
+        assert_eq!(apply(&preprocess, &lines), lines.concat());
+        assert_eq!(preprocess.weight(&['a', '(', 'b', ')']), 1.0);
+    }
+
+    #[test]
+    fn case_and_whitespace() {
+        let preprocess = Preprocess { fold_case: true, collapse_whitespace: true, ..Preprocess::default() };
+        assert!(!preprocess.is_identity());
+        // Runs of spaces are collapsed across lines, but not across tabs.
+        assert_eq!(apply(&preprocess, &["ÀB  c   ", "  D \t E"]), "àb c d \t e");
+    }
+
+    #[test]
+    fn newlines_and_tabs() {
+        let preprocess = Preprocess { newline: Some(' '), tab: Some('-'), ..Preprocess::default() };
+        assert_eq!(apply(&preprocess, &["a\tb\r\n", "c\n", "\r\n", "d"]), "a-b c  d");
+        // Without a newline character, carriage returns are kept.
+        let preprocess = Preprocess { tab: Some(' '), ..Preprocess::default() };
+        assert_eq!(apply(&preprocess, &["a\tb\r\n"]), "a b\r\n");
+    }
+
+    #[test]
+    fn digits_and_dropped_lines() {
+        let preprocess = Preprocess {
+            strip_digits: true,
+            drop_lines: Some(Regex::new("^#").unwrap()),
+            ..Preprocess::default()
+        };
+        let (mut out, mut space) = (String::new(), false);
+        assert!(!preprocess.line("# 1 comment\n", &mut out, &mut space));
+        assert!(out.is_empty());
+        // The expression sees lines without their line ending.
+        let preprocess = Preprocess { drop_lines: Some(Regex::new("x$").unwrap()), ..preprocess };
+        assert_eq!(apply(&preprocess, &["ab12c\n", "drop x\r\n", "#7 kept\n"]), "ab\0\0c\n#\0 kept\n");
+    }
+
+    #[test]
+    fn description() {
+        let preprocess = Preprocess {
+            fold_case: true,
+            collapse_whitespace: true,
+            newline: Some(' '),
+            tab: Some(' '),
+            strip_digits: true,
+            drop_lines: Some(Regex::new("^#").unwrap()),
+            ..Preprocess::default()
+        };
+        assert_eq!(
+            preprocess.to_string(),
+            "fold case, collapse whitespace, newline as ' ', tab as ' ', strip digits, drop lines matching ^#"
+        );
+    }
+
+    fn code(autopairs: bool) -> Preprocess {
+        Preprocess {
+            code: Some(Code {
+                extensions: vec!["rs".to_string(), "py".to_string()],
+                autopairs,
+                identifier_weight: 0.5,
+                punctuation_weight: 2.0,
+            }),
+            ..Preprocess::default()
+        }
+    }
+
+    #[test]
+    fn code_files() {
+        let code = code(false).code.unwrap();
+        assert!(code.includes(std::path::Path::new("src/main.rs")));
+        assert!(code.includes(std::path::Path::new("a.b.py")));
+        assert!(!code.includes(std::path::Path::new("README.md")));
+        assert!(!code.includes(std::path::Path::new("rs")));
+        assert!(!code.includes(std::path::Path::new("main.RS")));
+    }
+
+    #[test]
+    fn indentation_and_autopairs() {
+        let lines = ["fn f(a: [u8]) {\n", "\t  let s = \"x\" + \"y\";\n", "    }\n"];
+        assert_eq!(apply(&code(false), &lines), "fn f(a: [u8]) {\nlet s = \"x\" + \"y\";\n}\n");
+        assert_eq!(apply(&code(true), &lines), "fn f(a: [u8\0\0 {\nlet s = \"x\0 + \"y\0;\n\0\n");
+    }
+
+    #[test]
+    fn code_weights() {
+        let preprocess = code(false);
+        assert_eq!(preprocess.weight(&['f', 'o', 'o', '_']), 0.5);
+        assert_eq!(preprocess.weight(&['a', ' ', 'b', '2']), 0.5);
+        assert_eq!(preprocess.weight(&['a', '.', 'b', 'c']), 2.0);
+        assert_eq!(
+            code(true).to_string(),
+            "code (rs, py), strip indentation, drop autopaired characters, weight identifiers 0.5 and punctuation 2"
+        );
+    }
+}
@@ -0,0 +1,117 @@
+/// Live progress display for long running optimisations, drawn on stderr.
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Minimum time between two redraws of the progress line.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct Progress {
+    label: String,
+    total: usize,
+    done: AtomicUsize,
+    skipped: AtomicUsize,
+    // Bits of the best penalty seen so far, stored as `f64`.
+    best: AtomicU64,
+    started: Instant,
+    last_draw: Mutex<Instant>,
+    enabled: bool,
+}
+
+impl Progress {
+    /// Creates a progress display for `total` evaluations. Nothing is drawn
+    /// if `enabled` is false or stderr is not a terminal.
+    pub fn new(label: String, total: usize, enabled: bool) -> Progress {
+        let now = Instant::now();
+        Progress {
+            label,
+            total,
+            done: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+            best: AtomicU64::new(f64::INFINITY.to_bits()),
+            started: now,
+            last_draw: Mutex::new(now),
+            enabled: enabled && io::stderr().is_terminal(),
+        }
+    }
+
+    /// Marks `n` evaluations as done without counting them towards the
+    /// throughput, e.g. when resuming from a checkpoint.
+    pub fn skip(&self, n: usize) {
+        self.done.fetch_add(n, Ordering::Relaxed);
+        self.skipped.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn tick(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+        if !self.enabled {
+            return;
+        }
+        // Only one thread draws at a time; the others skip this tick.
+        if let Ok(mut last_draw) = self.last_draw.try_lock() {
+            if last_draw.elapsed() >= REDRAW_INTERVAL {
+                *last_draw = Instant::now();
+                self.draw();
+            }
+        }
+    }
+
+    pub fn set_best(&self, penalty: f64) {
+        let _ = self
+            .best
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                if penalty < f64::from_bits(bits) {
+                    Some(penalty.to_bits())
+                } else {
+                    None
+                }
+            });
+    }
+
+    /// Clears the progress line so that regular output can continue.
+    pub fn finish(&self) {
+        if self.enabled {
+            eprint!("\r\x1b[K");
+            let _ = io::stderr().flush();
+        }
+    }
+
+    fn draw(&self) {
+        let done = self.done.load(Ordering::Relaxed);
+        let best = f64::from_bits(self.best.load(Ordering::Relaxed));
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let evaluated = done - self.skipped.load(Ordering::Relaxed);
+        let rate = if elapsed > 0.0 { evaluated as f64 / elapsed } else { 0.0 };
+        let eta = if rate > 0.0 && self.total > done {
+            format_duration((self.total - done) as f64 / rate)
+        } else {
+            "-".to_string()
+        };
+        let best = if best.is_finite() {
+            format!("{:.4}", best)
+        } else {
+            "-".to_string()
+        };
+        eprint!(
+            "\r\x1b[K{}: {}/{} ({:.1}%)  best {}  {:.0} evals/s  ETA {}",
+            self.label,
+            done,
+            self.total,
+            100.0 * done as f64 / self.total.max(1) as f64,
+            best,
+            rate,
+            eta
+        );
+        let _ = io::stderr().flush();
+    }
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
@@ -0,0 +1,205 @@
+/// Named layouts: the built-in reference layouts plus the layout files of a
+/// user directory, which can be used with `-l NAME` and are scored by
+/// `run-refs`.
+use std::path::{Path, PathBuf};
+
+use crate::import;
+use crate::layout::{self, Layout};
+use crate::layout_file::LayoutInfo;
+use crate::Result;
+
+const BUILTINS: [(&str, &Layout); 11] = [
+    ("qwerty", &layout::QWERTY_LAYOUT),
+    ("dvorak", &layout::DVORAK_LAYOUT),
+    ("colemak", &layout::COLEMAK_LAYOUT),
+    ("colemak-dh", &layout::COLEMAK_DH_LAYOUT),
+    ("qgmlwy", &layout::QGMLWY_LAYOUT),
+    ("workman", &layout::WORKMAN_LAYOUT),
+    ("maltron", &layout::MALTRON_LAYOUT),
+    ("mtgap", &layout::MTGAP_LAYOUT),
+    ("capewell", &layout::CAPEWELL_LAYOUT),
+    ("arensito", &layout::ARENSITO_LAYOUT),
+    ("rsthd", &layout::RSTHD_LAYOUT),
+];
+
+// Environment variable overriding the default layout directory.
+const DIR_VAR: &str = "KEYGEN_LAYOUTS";
+
+#[derive(Debug)]
+pub struct Entry {
+    pub name: String,
+    pub layout: Layout,
+    pub info: LayoutInfo,
+    // File the layout was read from, `None` for built-ins.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct Registry {
+    entries: Vec<Entry>,
+}
+
+impl Registry {
+    /// Loads the built-ins and the layouts in `dir`, or in the default
+    /// directory if `dir` is `None`. Every file in the directory is a layout
+    /// named after the file without its extension; files replace built-ins of
+    /// the same name. Files that can't be read as a layout are skipped with a
+    /// warning.
+    pub fn load(dir: Option<&Path>) -> Result<Registry> {
+        let mut registry = Registry {
+            entries: BUILTINS
+                .iter()
+                .map(|(name, layout)| Entry {
+                    name: name.to_string(),
+                    layout: (*layout).clone(),
+                    info: LayoutInfo::default(),
+                    path: None,
+                })
+                .collect(),
+        };
+
+        let dir = match dir {
+            Some(dir) => dir.to_path_buf(),
+            None => match default_dir() {
+                Some(dir) if dir.is_dir() => dir,
+                _ => return Ok(registry),
+            },
+        };
+        let mut paths = std::fs::read_dir(&dir)
+            .map_err(|e| format!("Can't read layout directory {}: {}", dir.display(), e))?
+            .flatten()
+            .map(|file| file.path())
+            .filter(|path| path.is_file())
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        for path in paths {
+            let name = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(name) if !name.starts_with('.') => name.to_string(),
+                _ => continue,
+            };
+            let parsed = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|s| import::parse(&s, None).map_err(|e| e.to_string()));
+            match parsed {
+                Ok((layout, info)) => {
+                    let entry = Entry { name, layout, info, path: Some(path) };
+                    match registry.entries.iter_mut().find(|e| e.name == entry.name) {
+                        Some(existing) => *existing = entry,
+                        None => registry.entries.push(entry),
+                    }
+                }
+                Err(e) => eprintln!("Warning: skipping layout file {}: {}", path.display(), e),
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
+
+// `$KEYGEN_LAYOUTS`, or `keygen/layouts` in the XDG data directory.
+fn default_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(DIR_VAR) {
+        return Some(PathBuf::from(dir));
+    }
+    let data = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&std::env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(data.join("keygen/layouts"))
+}
+
+/// The `list-layouts` command.
+pub fn list(registry: &Registry) {
+    let width = registry.entries.iter().map(|e| e.name.len()).max().unwrap_or(0);
+    for entry in &registry.entries {
+        let source = match &entry.path {
+            Some(path) => path.display().to_string(),
+            None => "built-in".to_string(),
+        };
+        match &entry.info.author {
+            Some(author) => println!("{:width$}  {} (by {})", entry.name, source, author, width = width),
+            None => println!("{:width$}  {}", entry.name, source, width = width),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const COLEMAK_DH: &str = include_str!("../tests/fixtures/colemak-dh.layout");
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keygen-registry-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(registry: &Registry) -> Vec<&str> {
+        registry.entries().iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn builtins() {
+        let dir = temp_dir("empty");
+        let registry = Registry::load(Some(&dir)).unwrap();
+        assert_eq!(names(&registry), BUILTINS.iter().map(|(name, _)| *name).collect::<Vec<_>>());
+        let entry = registry.get("qwerty").unwrap();
+        assert_eq!(entry.layout, layout::QWERTY_LAYOUT);
+        assert!(entry.path.is_none());
+        assert!(registry.get("mine").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_replace_builtins() {
+        let dir = temp_dir("files");
+        fs::write(dir.join("qwerty.layout"), COLEMAK_DH).unwrap();
+        fs::write(dir.join("mine.txt"), COLEMAK_DH).unwrap();
+        fs::write(dir.join(".hidden.layout"), COLEMAK_DH).unwrap();
+        fs::write(dir.join("broken.layout"), "not a layout").unwrap();
+        fs::create_dir_all(dir.join("nested.layout")).unwrap();
+
+        let registry = Registry::load(Some(&dir)).unwrap();
+        let mut expected: Vec<&str> = BUILTINS.iter().map(|(name, _)| *name).collect();
+        expected.push("mine");
+        assert_eq!(names(&registry), expected);
+
+        let qwerty = registry.get("qwerty").unwrap();
+        assert_eq!(qwerty.layout, layout::COLEMAK_DH_LAYOUT);
+        assert_eq!(qwerty.path.as_deref(), Some(dir.join("qwerty.layout").as_path()));
+        let mine = registry.get("mine").unwrap();
+        assert_eq!(mine.layout, layout::COLEMAK_DH_LAYOUT);
+        assert_eq!(mine.path.as_deref(), Some(dir.join("mine.txt").as_path()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_directory() {
+        let dir = std::env::temp_dir().join(format!("keygen-registry-missing-{}", std::process::id()));
+        let error = Registry::load(Some(&dir)).unwrap_err().to_string();
+        assert!(error.starts_with("Can't read layout directory "), "{}", error);
+    }
+
+    // The only test that touches `$KEYGEN_LAYOUTS`.
+    #[test]
+    fn directory_from_environment() {
+        let dir = temp_dir("env");
+        fs::write(dir.join("mine.layout"), COLEMAK_DH).unwrap();
+        std::env::set_var(DIR_VAR, &dir);
+        assert_eq!(default_dir(), Some(dir.clone()));
+        let registry = Registry::load(None).unwrap();
+        std::env::remove_var(DIR_VAR);
+        assert_eq!(registry.get("mine").unwrap().layout, layout::COLEMAK_DH_LAYOUT);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,382 @@
+/// Self-contained HTML reports summarising a `run` or `refine`.
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::Instant;
+
+use strum::IntoEnumIterator;
+
+use crate::annealing::Trajectory;
+use crate::app::Config;
+use crate::layout::{Finger, Hand, Layout};
+use crate::penalty::{Corpus, LayoutPenalty, NGram, PenaltyVar};
+use crate::sampling;
+use crate::svg::{self, Metric};
+use crate::Result;
+
+// Charts are downsampled to at most this many points per series.
+const MAX_CHART_POINTS: usize = 1000;
+
+const CHART_WIDTH: f64 = 720.0;
+const CHART_HEIGHT: f64 = 240.0;
+const CHART_MARGIN: f64 = 48.0;
+
+pub enum Convergence<'a> {
+    Annealing(&'a Trajectory),
+    // Scaled penalty after every sweep, starting with the initial layout.
+    Refinement(&'a [f64]),
+}
+
+pub struct Report<'a> {
+    pub command: &'a str,
+    pub config: &'a Config,
+    pub corpus: &'a Corpus,
+    pub initial: (&'a Layout, &'a LayoutPenalty),
+    pub best: (&'a Layout, &'a LayoutPenalty),
+    pub convergence: Convergence<'a>,
+}
+
+pub fn write_to_file<P: AsRef<Path>>(path: P, report: &Report) -> Result<()> {
+    std::fs::write(path, render(report))?;
+    Ok(())
+}
+
+pub fn render(report: &Report) -> String {
+    let config = report.config;
+    let (init_layout, init_penalty) = report.initial;
+    let (best_layout, best_penalty) = report.best;
+    let metric = config.svg.as_ref().map(|o| o.metric).unwrap_or(Metric::Usage);
+
+    let mut html = String::new();
+    html.push_str(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>keygen report</title>\n<style>\n",
+    );
+    html.push_str(
+        "body { font-family: sans-serif; margin: 2em; color: #222; }\n\
+         table { border-collapse: collapse; margin-bottom: 1.5em; }\n\
+         th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: right; }\n\
+         th:first-child, td:first-child { text-align: left; }\n\
+         pre { background: #f4f4f4; padding: 8px; display: inline-block; }\n\
+         .layouts { display: flex; flex-wrap: wrap; gap: 2em; }\n",
+    );
+    html.push_str("</style>\n</head>\n<body>\n");
+
+    let _ = writeln!(html, "<h1>keygen {}</h1>", escape(report.command));
+    html.push_str("<table>\n");
+    for (path, weight) in &config.corpora {
+        let _ = writeln!(
+            html,
+            "<tr><td>Corpus</td><td>{} (weight {})</td></tr>",
+            escape(&path.display().to_string()),
+            weight
+        );
+    }
+    let _ = writeln!(
+        html,
+        "<tr><td>Preprocessing</td><td>{}</td></tr>",
+        escape(&config.preprocess.to_string())
+    );
+    if config.sampling.holdout > 0.0 {
+        let _ = writeln!(
+            html,
+            "<tr><td>Held out</td><td>{:.0}% of {}</td></tr>",
+            config.sampling.holdout * 100.0,
+            if config.sampling.by_document { "documents" } else { "lines" }
+        );
+    }
+    if config.sampling.bootstrap > 0 {
+        let _ = writeln!(html, "<tr><td>Bootstrap resamples</td><td>{}</td></tr>", config.sampling.bootstrap);
+    }
+    // What the context costs: the unique n-grams are held in memory and every
+    // evaluation of a layout goes through all of them.
+    let start = Instant::now();
+    best_layout.par_penalize(report.corpus);
+    let _ = writeln!(
+        html,
+        "<tr><td>Context</td><td>{} keys: {} unique n-grams, about {:.1} MiB, {:.1} ms per layout</td></tr>",
+        config.context,
+        report.corpus.ngrams.len(),
+        ngram_memory(report.corpus) as f64 / (1 << 20) as f64,
+        start.elapsed().as_secs_f64() * 1000.0
+    );
+    let _ = writeln!(html, "<tr><td>Swaps per iteration</td><td>{}</td></tr>", config.swaps);
+    if report.command == "run" {
+        let _ = writeln!(html, "<tr><td>Repetitions</td><td>{}</td></tr>", config.repetition);
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Summary</h2>\n<table>\n<tr><th></th><th>Initial</th><th>Best</th><th>Change</th></tr>\n");
+    let _ = writeln!(
+        html,
+        "<tr><td>Total penalty</td><td>{:.0}</td><td>{:.0}</td><td>{:+.2}%</td></tr>",
+        init_penalty.total,
+        best_penalty.total,
+        relative_change(init_penalty.total, best_penalty.total)
+    );
+    let _ = writeln!(
+        html,
+        "<tr><td>Scaled penalty</td><td>{:.4}</td><td>{:.4}</td><td>{:+.2}%</td></tr>",
+        init_penalty.scaled,
+        best_penalty.scaled,
+        relative_change(init_penalty.scaled, best_penalty.scaled)
+    );
+    if let (Some((init, _)), Some((best, _))) = (&init_penalty.held_out, &best_penalty.held_out) {
+        let _ = writeln!(
+            html,
+            "<tr><td>Held-out scaled penalty</td><td>{:.4}</td><td>{:.4}</td><td>{:+.2}%</td></tr>",
+            init,
+            best,
+            relative_change(*init, *best)
+        );
+    }
+    let part = if best_penalty.held_out.is_some() { "held-out " } else { "" };
+    let (init_resamples, best_resamples) = (
+        sampling::comparison_resamples(init_penalty),
+        sampling::comparison_resamples(best_penalty),
+    );
+    if let (Some(init), Some(best), Some(difference)) = (
+        sampling::interval(init_resamples),
+        sampling::interval(best_resamples),
+        sampling::difference(best_resamples, init_resamples),
+    ) {
+        let _ = writeln!(
+            html,
+            "<tr><td>95% CI of {}scaled penalty</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            part,
+            sampling::format_interval(init),
+            sampling::format_interval(best),
+            sampling::format_interval(difference)
+        );
+    }
+    html.push_str("</table>\n");
+
+    if !best_penalty.parts.is_empty() {
+        html.push_str("<h2>Corpora</h2>\n<table>\n<tr><th>Corpus</th><th>Share</th><th>Initial</th><th>Best</th><th>Change</th></tr>\n");
+        for ((name, share, init), (_, _, best)) in init_penalty.parts.iter().zip(&best_penalty.parts) {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{:.1}%</td><td>{:.4}</td><td>{:.4}</td><td>{:+.2}%</td></tr>",
+                escape(name),
+                share * 100.0,
+                init,
+                best,
+                relative_change(*init, *best)
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("<h2>Layouts</h2>\n<div class=\"layouts\">\n");
+    for (name, layout, penalty) in [
+        ("Initial", init_layout, init_penalty),
+        ("Best", best_layout, best_penalty),
+    ]
+    .iter()
+    {
+        let _ = writeln!(html, "<div>\n<h3>{}</h3>", name);
+        html.push_str(&svg::render(layout, penalty, metric, true));
+        let _ = writeln!(html, "<br>\n<pre>{}</pre>\n</div>", escape(&layout.to_string()));
+    }
+    html.push_str("</div>\n");
+
+    html.push_str("<h2>Finger usage</h2>\n<table>\n<tr><th>Finger</th><th>Initial</th><th>Best</th></tr>\n");
+    for hand in Hand::iter() {
+        for finger in Finger::iter() {
+            let _ = writeln!(
+                html,
+                "<tr><td>{} {}</td><td>{:.4}</td><td>{:.4}</td></tr>",
+                hand,
+                finger,
+                finger_usage(init_penalty, hand, finger),
+                finger_usage(best_penalty, hand, finger)
+            );
+        }
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Penalty breakdown</h2>\n<table>\n<tr><th>Penalty</th><th>Initial</th><th>Best</th><th>Top n-grams (best)</th></tr>\n");
+    for var in PenaltyVar::iter() {
+        let mut high_keys: Vec<(&str, f64)> = best_penalty
+            .high_keys
+            .get(&var)
+            .map(|keys| keys.iter().map(|(s, &v)| (s.as_str(), v)).collect())
+            .unwrap_or_default();
+        high_keys.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        let top = high_keys
+            .iter()
+            .take(5)
+            .map(|(s, v)| format!("<code>{}</code>&nbsp;{:.0}", escape(&s.replace(' ', "␣")), v))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{:.0}</td><td>{:.0}</td><td>{}</td></tr>",
+            var,
+            penalty_total(init_penalty, var),
+            penalty_total(best_penalty, var),
+            top
+        );
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Convergence</h2>\n");
+    match report.convergence {
+        Convergence::Annealing(trajectory) => {
+            let steps = &trajectory.steps;
+            let x = |i: usize| steps[i].iteration as f64;
+            html.push_str(&line_chart(
+                "Penalty (scaled)",
+                &[
+                    ("current", "#1f77b4", downsample(steps.len(), |i| (x(i), steps[i].penalty))),
+                    ("best", "#d62728", downsample(steps.len(), |i| (x(i), steps[i].best_penalty))),
+                ],
+            ));
+            html.push_str(&line_chart(
+                "Temperature",
+                &[("temperature", "#ff7f0e", downsample(steps.len(), |i| (x(i), steps[i].temperature)))],
+            ));
+            let rate = trajectory.acceptance_rate(config.trace_window);
+            html.push_str(&line_chart(
+                &format!("Acceptance rate (last {} iterations)", config.trace_window),
+                &[("acceptance rate", "#2ca02c", downsample(steps.len(), |i| (x(i), rate[i])))],
+            ));
+        }
+        Convergence::Refinement(sweeps) => {
+            html.push_str(&line_chart(
+                "Penalty (scaled) after each sweep",
+                &[("penalty", "#1f77b4", downsample(sweeps.len(), |i| (i as f64, sweeps[i])))],
+            ));
+        }
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+// Name, colour and points of a line in a chart.
+type Series<'a> = (&'a str, &'a str, Vec<(f64, f64)>);
+
+fn line_chart(title: &str, series: &[Series]) -> String {
+    let points = series.iter().flat_map(|(_, _, points)| points.iter());
+    let (x_min, x_max, y_min, y_max) = points.fold(
+        (f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY),
+        |(x0, x1, y0, y1), &(x, y)| (x0.min(x), x1.max(x), y0.min(y), y1.max(y)),
+    );
+    if !x_min.is_finite() {
+        return String::new();
+    }
+    let x_span = if x_max > x_min { x_max - x_min } else { 1.0 };
+    let y_span = if y_max > y_min { y_max - y_min } else { 1.0 };
+    let plot_w = CHART_WIDTH - 2.0 * CHART_MARGIN;
+    let plot_h = CHART_HEIGHT - 2.0 * CHART_MARGIN;
+    let px = |x: f64| CHART_MARGIN + (x - x_min) / x_span * plot_w;
+    let py = |y: f64| CHART_HEIGHT - CHART_MARGIN - (y - y_min) / y_span * plot_h;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<h3>{}</h3>
+<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="11">"#,
+        escape(title),
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT
+    );
+    let _ = writeln!(
+        svg,
+        r##"<rect x="{m}" y="{m}" width="{}" height="{}" fill="none" stroke="#999999"/>"##,
+        plot_w,
+        plot_h,
+        m = CHART_MARGIN
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="end">{:.4}</text><text x="{}" y="{}" text-anchor="end">{:.4}</text>"#,
+        CHART_MARGIN - 4.0,
+        py(y_max) + 4.0,
+        y_max,
+        CHART_MARGIN - 4.0,
+        py(y_min) + 4.0,
+        y_min
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="middle">{}</text><text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+        px(x_min),
+        CHART_HEIGHT - CHART_MARGIN + 16.0,
+        x_min,
+        px(x_max),
+        CHART_HEIGHT - CHART_MARGIN + 16.0,
+        x_max
+    );
+    for (n, (name, colour, points)) in series.iter().enumerate() {
+        let path = points
+            .iter()
+            .map(|&(x, y)| format!("{:.1},{:.1}", px(x), py(y)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let _ = writeln!(
+            svg,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="1.5"/>"#,
+            path, colour
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" fill="{}">{}</text>"#,
+            CHART_MARGIN + 8.0 + 120.0 * n as f64,
+            CHART_MARGIN - 8.0,
+            colour,
+            escape(name)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn downsample<F>(len: usize, point: F) -> Vec<(f64, f64)>
+where
+    F: Fn(usize) -> (f64, f64),
+{
+    let step = (len / MAX_CHART_POINTS).max(1);
+    let mut points: Vec<_> = (0..len).step_by(step).map(&point).collect();
+    if len > 0 && !(len - 1).is_multiple_of(step) {
+        points.push(point(len - 1));
+    }
+    points
+}
+
+fn finger_usage(penalty: &LayoutPenalty, hand: Hand, finger: Finger) -> f64 {
+    penalty
+        .usage
+        .get(&hand)
+        .and_then(|usage| usage.get(&finger))
+        .cloned()
+        .unwrap_or(0.0)
+}
+
+fn penalty_total(penalty: &LayoutPenalty, var: PenaltyVar) -> f64 {
+    penalty
+        .high_keys
+        .get(&var)
+        .map(|keys| keys.values().sum())
+        .unwrap_or(0.0)
+}
+
+// Approximate size of the n-gram counts of a corpus in bytes, including the
+// hash table's control byte per entry.
+fn ngram_memory(corpus: &Corpus) -> usize {
+    corpus.ngrams.capacity() * (std::mem::size_of::<(NGram, f64)>() + 1)
+}
+
+fn relative_change(from: f64, to: f64) -> f64 {
+    if from != 0.0 {
+        100.0 * (to - from) / from.abs()
+    } else {
+        0.0
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,292 @@
+/// Holding out part of the text corpora to test layouts on, and bootstrapping
+/// confidence intervals of penalties.
+///
+/// Both work on units of text: lines, or whole files when splitting by
+/// document. Units are assigned by a hash of their position, so that the
+/// split is the same on every run and doesn't depend on reading in parallel.
+/// For bootstrapping, the units are divided into blocks, which are resampled
+/// with replacement.
+use rand::{Rng, SeedableRng, StdRng};
+use rayon::prelude::*;
+
+use crate::layout::Layout;
+use crate::penalty::{Corpus, LayoutPenalty};
+
+/// Number of blocks that the units are divided into for bootstrapping.
+pub const BLOCKS: usize = 100;
+
+// Resamples are drawn from a fixed seed, so that the same ones are used for
+// every layout and their penalties can be compared pairwise.
+const SEED: usize = 0x5eed;
+
+#[derive(Debug, Clone, Default)]
+pub struct Sampling {
+    // Fraction of the units held out from optimisation.
+    pub holdout: f64,
+    // Split by file rather than by line.
+    pub by_document: bool,
+    // Number of bootstrap resamples, or 0 for none.
+    pub bootstrap: usize,
+}
+
+impl Sampling {
+    pub fn is_active(&self) -> bool {
+        self.holdout > 0.0 || self.bootstrap > 0
+    }
+
+    /// Number of bins that the counts of a corpus are divided into: the
+    /// blocks of the training part, followed by those of the held-out part.
+    pub fn bins(&self) -> usize {
+        2 * self.blocks()
+    }
+
+    fn blocks(&self) -> usize {
+        if self.bootstrap > 0 {
+            BLOCKS
+        } else {
+            1
+        }
+    }
+
+    /// The bin of line `line` of the `file`th file of a corpus.
+    pub fn bin(&self, file: usize, line: usize) -> usize {
+        let unit = if self.by_document {
+            mix(file as u64)
+        } else {
+            mix(mix(file as u64) ^ line as u64)
+        };
+        let held_out = ((unit >> 11) as f64 / (1u64 << 53) as f64) < self.holdout;
+        let block = (mix(unit) % self.blocks() as u64) as usize;
+        if held_out {
+            self.blocks() + block
+        } else {
+            block
+        }
+    }
+
+    /// Combines the bins into a corpus with its held-out part and bootstrap
+    /// blocks.
+    pub fn assemble(&self, bins: Vec<Corpus>) -> Corpus {
+        let mut bins = bins.into_iter();
+        let part = |bins: &mut std::vec::IntoIter<Corpus>| {
+            let blocks: Vec<Corpus> = bins.take(self.blocks()).collect();
+            let mut corpus = Corpus::default();
+            for block in &blocks {
+                corpus.merge(block);
+            }
+            if self.bootstrap > 0 {
+                corpus.bootstrap = Some(Bootstrap { blocks, resamples: self.bootstrap });
+            }
+            corpus
+        };
+        let mut corpus = part(&mut bins);
+        if self.holdout > 0.0 {
+            corpus.held_out = Some(Box::new(part(&mut bins)));
+        }
+        corpus
+    }
+}
+
+// SplitMix64's finaliser, which spreads consecutive numbers over all bits.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// A corpus divided into blocks, which add up to the corpus.
+#[derive(Clone)]
+pub struct Bootstrap {
+    pub blocks: Vec<Corpus>,
+    pub resamples: usize,
+}
+
+impl Bootstrap {
+    pub fn new(resamples: usize) -> Bootstrap {
+        Bootstrap { blocks: Vec::new(), resamples }
+    }
+
+    /// Adds the blocks of another bootstrap, multiplied by `scale`.
+    pub fn add(&mut self, other: &Bootstrap, scale: f64) {
+        if self.blocks.len() < other.blocks.len() {
+            self.blocks.resize_with(other.blocks.len(), Corpus::default);
+        }
+        for (block, other) in self.blocks.iter_mut().zip(&other.blocks) {
+            block.add(other, scale);
+        }
+    }
+
+    /// Scaled penalties of a layout on every resample.
+    pub fn scaled(&self, layout: &Layout) -> Vec<f64> {
+        let blocks: Vec<(f64, f64)> = self
+            .blocks
+            .par_iter()
+            .map(|block| (layout.penalize(block), block.len))
+            .collect();
+        if blocks.is_empty() {
+            return Vec::new();
+        }
+        let mut rng: StdRng = SeedableRng::from_seed(&[SEED][..]);
+        (0..self.resamples)
+            .filter_map(|_| {
+                let (mut penalty, mut len) = (0.0, 0.0);
+                for _ in 0..blocks.len() {
+                    let (p, l) = blocks[rng.gen_range(0, blocks.len())];
+                    penalty += p;
+                    len += l;
+                }
+                if len > 0.0 {
+                    Some(penalty / len)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// The central 95% of the values, or `None` without any.
+pub fn interval(values: &[f64]) -> Option<(f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let at = |q: f64| sorted[((q * (sorted.len() - 1) as f64).round() as usize).min(sorted.len() - 1)];
+    Some((at(0.025), at(0.975)))
+}
+
+/// 95% interval of the difference between the penalties of two layouts on the
+/// same resamples.
+pub fn difference(a: &[f64], b: &[f64]) -> Option<(f64, f64)> {
+    if a.len() != b.len() {
+        return None;
+    }
+    let differences: Vec<f64> = a.iter().zip(b).map(|(a, b)| a - b).collect();
+    interval(&differences)
+}
+
+pub fn format_interval((low, high): (f64, f64)) -> String {
+    format!("[{:.4}, {:.4}]", low, high)
+}
+
+/// Resamples that layouts are compared on: those of the held-out part if
+/// there is one, since the others are optimised on.
+pub fn comparison_resamples(penalty: &LayoutPenalty) -> &[f64] {
+    match &penalty.held_out {
+        Some((_, resamples)) => resamples,
+        None => &penalty.resamples,
+    }
+}
+
+/// Prints the interval of the difference in scaled penalty between two
+/// layouts, if the corpus is bootstrapped.
+pub fn print_difference(name: &str, a: &LayoutPenalty, b: &LayoutPenalty) {
+    let part = if a.held_out.is_some() { "held-out " } else { "" };
+    if let Some(interval) = difference(comparison_resamples(a), comparison_resamples(b)) {
+        println!("Difference to {}: {}scaled 95% CI: {}", name, part, format_interval(interval));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::QWERTY_LAYOUT;
+    use crate::ngrams;
+
+    // Share of 10000 lines of a file that are held out.
+    fn held_out_share(sampling: &Sampling) -> f64 {
+        let held_out = (0..10000).filter(|&line| sampling.bin(0, line) >= sampling.blocks()).count();
+        held_out as f64 / 10000.0
+    }
+
+    #[test]
+    fn bins() {
+        let holdout = Sampling { holdout: 0.2, ..Sampling::default() };
+        assert!(!Sampling::default().is_active());
+        assert!(holdout.is_active());
+        assert_eq!(holdout.bins(), 2);
+        let bootstrap = Sampling { bootstrap: 10, ..Sampling::default() };
+        assert!(bootstrap.is_active());
+        assert_eq!(bootstrap.bins(), 2 * BLOCKS);
+
+        assert_eq!(held_out_share(&Sampling::default()), 0.0);
+        assert_eq!(held_out_share(&Sampling { holdout: 1.0, ..Sampling::default() }), 1.0);
+        let share = held_out_share(&holdout);
+        assert!((share - 0.2).abs() < 0.02, "{}", share);
+
+        // Lines are spread over all blocks, the same way every time.
+        let bins: Vec<usize> = (0..10000).map(|line| bootstrap.bin(3, line)).collect();
+        assert!((0..BLOCKS).all(|block| bins.contains(&block)));
+        assert_eq!(bins, (0..10000).map(|line| bootstrap.bin(3, line)).collect::<Vec<_>>());
+        assert_ne!(bins, (0..10000).map(|line| bootstrap.bin(4, line)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn documents_stay_together() {
+        let sampling = Sampling { holdout: 0.5, by_document: true, bootstrap: 10 };
+        for file in 0..20 {
+            let bin = sampling.bin(file, 0);
+            assert!((0..100).all(|line| sampling.bin(file, line) == bin));
+        }
+        let held_out = (0..1000).filter(|&file| sampling.bin(file, 0) >= BLOCKS).count();
+        assert!((400..600).contains(&held_out), "{}", held_out);
+    }
+
+    #[test]
+    fn assemble() {
+        let sampling = Sampling { holdout: 0.5, by_document: false, bootstrap: 10 };
+        let bins: Vec<Corpus> = (0..sampling.bins())
+            .map(|bin| ngrams::parse(&format!("the \t{}\n", bin + 1), false, 4).unwrap())
+            .collect();
+        let corpus = sampling.assemble(bins);
+        // Bins 1 to 100 are trained on, and 101 to 200 held out.
+        assert_eq!(corpus.len, 5050.0);
+        let bootstrap = corpus.bootstrap.as_ref().unwrap();
+        assert_eq!((bootstrap.blocks.len(), bootstrap.resamples), (BLOCKS, 10));
+        assert_eq!(bootstrap.blocks[99].len, 100.0);
+        let held_out = corpus.held_out.as_ref().unwrap();
+        assert_eq!(held_out.len, 15050.0);
+        assert_eq!(held_out.bootstrap.as_ref().unwrap().blocks[0].len, 101.0);
+        assert!(held_out.held_out.is_none());
+
+        let sampling = Sampling { holdout: 0.5, ..Sampling::default() };
+        let bins = vec![ngrams::parse("the \t3\n", false, 4).unwrap(), ngrams::parse("and \t1\n", false, 4).unwrap()];
+        let corpus = sampling.assemble(bins);
+        assert_eq!(corpus.len, 3.0);
+        assert!(corpus.bootstrap.is_none());
+        assert_eq!(corpus.held_out.unwrap().len, 1.0);
+    }
+
+    #[test]
+    fn resampled_penalties() {
+        let blocks: Vec<Corpus> = ["the \t10\n", "and \t10\n", "ing \t10\nthe \t5\n"]
+            .iter()
+            .map(|table| ngrams::parse(table, false, 4).unwrap())
+            .collect();
+        let scaled: Vec<f64> = blocks.iter().map(|block| QWERTY_LAYOUT.penalize(block) / block.len).collect();
+        let bootstrap = Bootstrap { blocks, resamples: 50 };
+        let resamples = bootstrap.scaled(&QWERTY_LAYOUT);
+        assert_eq!(resamples.len(), 50);
+        let (min, max) = scaled.iter().fold((f64::MAX, f64::MIN), |(min, max), &s| (min.min(s), max.max(s)));
+        assert!(resamples.iter().all(|&r| r >= min - 1e-9 && r <= max + 1e-9));
+        assert!(resamples.iter().any(|&r| r != resamples[0]));
+        // Every layout is scored on the same resamples.
+        assert_eq!(resamples, bootstrap.scaled(&QWERTY_LAYOUT));
+        assert!(Bootstrap::new(50).scaled(&QWERTY_LAYOUT).is_empty());
+    }
+
+    #[test]
+    fn intervals() {
+        let values: Vec<f64> = (1..=101).rev().map(f64::from).collect();
+        assert_eq!(interval(&values), Some((4.0, 99.0)));
+        assert_eq!(interval(&[2.5]), Some((2.5, 2.5)));
+        assert_eq!(interval(&[]), None);
+
+        let offset: Vec<f64> = values.iter().map(|v| v - 1.0).collect();
+        assert_eq!(difference(&values, &offset), Some((1.0, 1.0)));
+        assert_eq!(difference(&values, &offset[1..]), None);
+        assert_eq!(format_interval((0.12346, 2.0)), "[0.1235, 2.0000]");
+    }
+}
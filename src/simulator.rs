@@ -2,28 +2,116 @@ extern crate itertools;
 /// Applies the math in annealing.rs to keyboard layouts.
 extern crate rand;
 
-use itertools::{FoldWhile, Itertools};
 use rayon::iter::ParallelBridge;
 use rayon::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 
 use crate::annealing;
+use crate::checkpoint::{self, Checkpoint};
+use crate::import;
 use crate::app::{self, Config};
 use crate::layout::{self, Layout};
 use crate::penalty::{self, Corpus};
+use crate::progress::Progress;
+use crate::report;
+use crate::sampling;
+use crate::svg;
 use crate::Result;
 use rand::Rng;
-use rand::{SeedableRng, StdRng};
 
-pub fn run<'a>(corpus: &Corpus, config: &Config) -> Result<()> {
+pub fn run(corpus: &Corpus, config: &Config) -> Result<()> {
     let init_penalty = config.layout.penalize_with_details(corpus);
+    let hash = checkpoint::config_hash(config, corpus);
 
-    let best_layout = (0..config.repetition)
-        .map(|n| {
-            println!("Started run {}/{}", n + 1, config.repetition);
-            simulated_annealing(corpus, config) // Returns best layout found during simulation
-        })
-        .min_by(|l1, l2| {
+    let (mut runs, mut current) = match &config.checkpoint {
+        Some(path) if config.resume => match Checkpoint::from_file(path, config, hash)? {
+            Checkpoint::Run { completed, current } => {
+                println!(
+                    "Resuming from {} after {} finished run(s)",
+                    path.display(),
+                    completed.len()
+                );
+                (completed, current)
+            }
+            Checkpoint::Refine { .. } => unreachable!(),
+        },
+        _ => (Vec::new(), None),
+    };
+
+    while runs.len() < config.repetition {
+        let n = runs.len();
+        println!("Started run {}/{}", n + 1, config.repetition);
+        let state = current.take().unwrap_or_else(|| {
+            checkpoint::RunState::new(
+                &config.layout,
+                config.layout.par_penalize(corpus) / corpus.len,
+            )
+        });
+        let progress = Progress::new(
+            format!("run {}/{}", n + 1, config.repetition),
+            annealing::get_simulation_range().len(),
+            !config.debug,
+        );
+        progress.skip(state.iteration);
+
+        let save = |state: &checkpoint::RunState| -> Result<()> {
+            if let Some(path) = &config.checkpoint {
+                let completed = runs
+                    .iter()
+                    .map(|(layout, trajectory): &(Layout, annealing::Trajectory)| {
+                        (layout.clone(), trajectory.clone())
+                    })
+                    .collect();
+                Checkpoint::Run {
+                    completed,
+                    current: Some(state.clone()),
+                }
+                .write_to_file(path, hash)?;
+            }
+            Ok(())
+        };
+        let (state, interrupted) = simulated_annealing(corpus, config, &progress, state, save)?;
+        progress.finish();
+
+        if interrupted {
+            write_trace(config, &runs, Some(&state.trajectory))?;
+            if let Some(path) = &config.trace {
+                println!("Trace written to {}", path.display());
+            }
+            // Write the best layout found so far before giving up.
+            let best_layout = runs
+                .iter()
+                .map(|(layout, _)| layout)
+                .chain(std::iter::once(&state.best_layout))
+                .min_by(|l1, l2| {
+                    let p1 = l1.par_penalize(corpus);
+                    let p2 = l2.par_penalize(corpus);
+                    p1.partial_cmp(&p2).unwrap()
+                })
+                .unwrap();
+            return interrupt(config, best_layout, "winner.layout");
+        }
+        runs.push((state.best_layout, state.trajectory));
+        write_trace(config, &runs, None)?;
+    }
+
+    if let Some(path) = &config.checkpoint {
+        Checkpoint::Run {
+            completed: runs.clone(),
+            current: None,
+        }
+        .write_to_file(path, hash)?;
+    }
+
+    if let Some(path) = &config.trace {
+        println!("Trace written to {}", path.display());
+    }
+
+    let (best_layout, trajectory) = runs
+        .into_iter()
+        .min_by(|(l1, _), (l2, _)| {
             let p1 = l1.par_penalize(corpus);
             let p2 = l2.par_penalize(corpus);
             p1.partial_cmp(&p2).unwrap()
@@ -34,165 +122,476 @@ pub fn run<'a>(corpus: &Corpus, config: &Config) -> Result<()> {
     println!("Initial layout:");
     println!("{}", config.layout);
     println!("{}", init_penalty);
-    println!("");
+    println!();
 
     println!("BestLayout:");
     println!("{}", best_layout);
-    println!("{}", best_layout.penalize_with_details(corpus));
+    let best_penalty = best_layout.penalize_with_details(corpus);
+    println!("{}", best_penalty);
+    sampling::print_difference("initial layout", &best_penalty, &init_penalty);
+    write_svg(config, &best_layout, &best_penalty)?;
+    if let Some(path) = &config.report {
+        report::write_to_file(
+            path,
+            &report::Report {
+                command: "run",
+                config,
+                corpus,
+                initial: (&config.layout, &init_penalty),
+                best: (&best_layout, &best_penalty),
+                convergence: report::Convergence::Annealing(&trajectory),
+            },
+        )?;
+        println!("Report written to {}", path.display());
+    }
     best_layout.write_to_file(
         config
             .output
             .as_ref()
             .unwrap_or(&PathBuf::from("winner.layout")),
+        &config.layout_info.derived(),
     )?;
 
     Ok(())
 }
 
-// Returns best layout found during the simulation, not neccessarily the last one
-fn simulated_annealing(corpus: &Corpus, config: &Config) -> Layout {
-    let init_penalty = config.layout.par_penalize(corpus) / corpus.len as f64;
+// Writes the trajectories of the finished runs and, when interrupted, of the
+// current one to the trace file, if requested.
+fn write_trace(
+    config: &Config,
+    runs: &[(Layout, annealing::Trajectory)],
+    current: Option<&annealing::Trajectory>,
+) -> Result<()> {
+    if let Some(path) = &config.trace {
+        let mut file = BufWriter::new(File::create(path)?);
+        annealing::Trajectory::write_csv_header(&mut file)?;
+        for (n, trajectory) in runs.iter().map(|(_, trajectory)| trajectory).chain(current).enumerate() {
+            trajectory.write_csv(&mut file, n + 1, config.trace_window, config.trace_every)?;
+        }
+        file.flush()?;
+    }
+    Ok(())
+}
 
-    let mut best_layout = config.layout.clone();
-    let mut best_penalty = init_penalty;
+// Continues the simulation from `state` until it is finished or interrupted.
+// The returned state holds the best layout found during the simulation, not
+// neccessarily the last one, and the trajectory of the simulation. If
+// checkpoints are enabled, `save` is called every `checkpoint_every`
+// iterations and when interrupted; the state includes the position of the
+// random number generator, so that the simulation is resumed exactly.
+fn simulated_annealing<F>(
+    corpus: &Corpus,
+    config: &Config,
+    progress: &Progress,
+    mut state: checkpoint::RunState,
+    save: F,
+) -> Result<(checkpoint::RunState, bool)>
+where
+    F: Fn(&checkpoint::RunState) -> Result<()>,
+{
+    progress.set_best(state.best_penalty);
+    let mut rng = checkpoint::RunRng::new(state.draws);
+    if config.checkpoint.is_some() {
+        save(&state)?;
+    }
 
-    let seed: &[_] = &[1, 2, 3, 4];
-    let mut rng : StdRng = SeedableRng::from_seed(seed);
+    for i in (state.iteration + 1)..annealing::get_simulation_range().end {
+        if checkpoint::interrupted() {
+            if config.checkpoint.is_some() {
+                save(&state)?;
+            }
+            return Ok((state, true));
+        }
 
-    annealing::get_simulation_range().fold(
-        (config.layout.clone(), init_penalty),
-        |(accepted_layout, accepted_penalty), i| {
-            // Copy and shuffle this iteration of the layout.
-            let mut new_layout = accepted_layout.clone();
-            new_layout.shuffle(1+rng.gen_range(0,config.swaps), &mut rng);
+        // Copy and shuffle this iteration of the layout.
+        let mut new_layout = state.layout.clone();
+        new_layout.shuffle(1 + rng.gen_range(0, config.swaps), &config.shuffle_mask, &mut rng);
 
-            // Probabilistically accept worse transitions; always accept better
-            // transitions.
-            let new_penalty = new_layout.par_penalize(corpus) / corpus.len as f64;
+        // Probabilistically accept worse transitions; always accept better
+        // transitions.
+        let new_penalty = new_layout.par_penalize(corpus) / corpus.len;
+        progress.tick();
 
-            if annealing::accept_transition(new_penalty - accepted_penalty, i, &mut rng) {
-                if new_penalty < best_penalty {
-                    best_layout = new_layout.clone();
-                    best_penalty = new_penalty;
-                }
+        if annealing::accept_transition(new_penalty - state.penalty, i, &mut rng) {
+            if new_penalty < state.best_penalty {
+                state.best_layout = new_layout.clone();
+                state.best_penalty = new_penalty;
+                progress.set_best(state.best_penalty);
+            }
 
-                if config.debug {
-                    println!(
-                        "Iteration {} accepted with penalty {}. Current penalty: {}",
-                        i, new_penalty, accepted_penalty
-                    );
-                }
-                (new_layout, new_penalty)
-            } else {
-                if config.debug {
-                    println!(
-                        "Iteration {} not accepted with penalty {}. Current penalty: {}",
-                        i, new_penalty, accepted_penalty
-                    );
-                }
-                (accepted_layout, accepted_penalty)
+            if config.debug {
+                println!(
+                    "Iteration {} accepted with penalty {}. Current penalty: {}",
+                    i, new_penalty, state.penalty
+                );
             }
-        },
-    );
-    best_layout
+            state.trajectory.record(i, new_penalty, state.best_penalty, true);
+            state.layout = new_layout;
+            state.penalty = new_penalty;
+        } else {
+            if config.debug {
+                println!(
+                    "Iteration {} not accepted with penalty {}. Current penalty: {}",
+                    i, new_penalty, state.penalty
+                );
+            }
+            state.trajectory.record(i, state.penalty, state.best_penalty, false);
+        }
+        state.iteration = i;
+        state.draws = rng.draws;
+
+        if config.checkpoint.is_some() && i % config.checkpoint_every == 0 {
+            save(&state)?;
+        }
+    }
+    Ok((state, false))
 }
 
-pub fn refine<'a>(corpus: &Corpus, config: &Config) -> Result<()> {
+pub fn refine(corpus: &Corpus, config: &Config) -> Result<()> {
     println!(
         "Start refining with {} swaps and initial layout:",
         config.swaps
     );
     println!("{}", config.layout);
-    println!("{}", config.layout.penalize_with_details(corpus));
+    let init_penalty = config.layout.penalize_with_details(corpus);
+    println!("{}", init_penalty);
 
-    let mut permutations = layout::LayoutPermutations::from_config(&config);
+    let hash = checkpoint::config_hash(config, corpus);
+    let mut permutations = layout::LayoutPermutations::from_config(config);
+    let (first_sweep, mut curr_layout, mut sweeps) = match &config.checkpoint {
+        Some(path) if config.resume => match Checkpoint::from_file(path, config, hash)? {
+            Checkpoint::Refine {
+                sweep,
+                layout,
+                sweeps,
+            } => {
+                println!("Resuming from {} after sweep {}:", path.display(), sweep);
+                println!("{}", layout);
+                (sweep + 1, layout, sweeps)
+            }
+            Checkpoint::Run { .. } => unreachable!(),
+        },
+        _ => (1, config.layout.clone(), vec![init_penalty.scaled]),
+    };
 
-    let best_layout = (1..)
-        .fold_while(config.layout.clone(), |curr_layout, n| {
-            let curr_penalty = curr_layout.penalize(corpus);
+    for n in first_sweep.. {
+        let curr_penalty = curr_layout.penalize(corpus);
 
-            // Test every layout within `num_swaps` swaps of the initial layout.
-            permutations.set_layout(&curr_layout);
-            let (best_layout, best_penalty) = permutations
-                .iter()
-                .par_bridge()
-                .map(|layout| {
-                    let penalty = layout.penalize(corpus);
-                    (layout, penalty)
-                })
-                .min_by(|(_, p1), (_, p2)| p1.partial_cmp(&p2).unwrap())
-                .unwrap_or((curr_layout.clone(), curr_penalty));
+        // Test every layout within `num_swaps` swaps of the initial layout.
+        permutations.set_layout(&curr_layout);
+        let progress = Progress::new(format!("sweep {}", n), permutations.len(), true);
+        progress.set_best(curr_penalty / corpus.len);
+        let (best_layout, best_penalty) = permutations
+            .iter()
+            .take_while(|_| !checkpoint::interrupted())
+            .par_bridge()
+            .map(|layout| {
+                let penalty = layout.penalize(corpus);
+                progress.set_best(penalty / corpus.len);
+                progress.tick();
+                (layout, penalty)
+            })
+            .min_by(|(_, p1), (_, p2)| p1.partial_cmp(p2).unwrap())
+            .unwrap_or((curr_layout.clone(), curr_penalty));
+        progress.finish();
 
-            // Keep going until swapping doesn't get us any more improvements.
-            if curr_layout.penalize(corpus) <= best_penalty {
-                FoldWhile::Done(curr_layout)
+        if checkpoint::interrupted() {
+            // The checkpoint still holds the start of this sweep, which is
+            // where a resumed refinement picks up again.
+            let best_layout = if best_penalty < curr_penalty {
+                &best_layout
             } else {
-                println!("Result of iteration {}:", n);
-                println!("{}", best_layout);
-                println!("{}", best_layout.penalize_with_details(corpus));
-                FoldWhile::Continue(best_layout)
+                &curr_layout
+            };
+            return interrupt(config, best_layout, "refined.layout");
+        }
+
+        // Keep going until swapping doesn't get us any more improvements.
+        if curr_penalty <= best_penalty {
+            break;
+        }
+        println!("Result of iteration {}:", n);
+        println!("{}", best_layout);
+        println!("{}", best_layout.penalize_with_details(corpus));
+        sweeps.push(best_penalty / corpus.len);
+        curr_layout = best_layout;
+
+        if let Some(path) = &config.checkpoint {
+            Checkpoint::Refine {
+                sweep: n,
+                layout: curr_layout.clone(),
+                sweeps: sweeps.clone(),
             }
-        })
-        .into_inner();
+            .write_to_file(path, hash)?;
+        }
+    }
+    let best_layout = curr_layout;
 
-    println!("");
+    println!();
     println!("Ultimate winner:");
     println!("{}", best_layout);
-    println!("{}", best_layout.penalize_with_details(corpus));
+    let best_penalty = best_layout.penalize_with_details(corpus);
+    println!("{}", best_penalty);
+    sampling::print_difference("initial layout", &best_penalty, &init_penalty);
+    write_svg(config, &best_layout, &best_penalty)?;
+    if let Some(path) = &config.report {
+        report::write_to_file(
+            path,
+            &report::Report {
+                command: "refine",
+                config,
+                corpus,
+                initial: (&config.layout, &init_penalty),
+                best: (&best_layout, &best_penalty),
+                convergence: report::Convergence::Refinement(&sweeps),
+            },
+        )?;
+        println!("Report written to {}", path.display());
+    }
     best_layout.write_to_file(
         config
             .output
             .as_ref()
             .unwrap_or(&PathBuf::from("refined.layout")),
+        &config.layout_info.derived(),
     )?;
     Ok(())
 }
 
-pub fn analyze(corpus: &Corpus) -> Result<()> {
-    let mut path = std::env::current_dir()?;
-    path.push("analyze");
-
-    std::fs::read_dir(path)
-        .map_err(|_| "No directory 'analyze' found in current path")?
-        .flatten()
-        .map(|file| -> Result<()> {
-            if file.file_type()?.is_file() {
-                let path = file.path();
-                let layout = Layout::from_string(&std::fs::read_to_string(&path)?).ok_or(
-                    format!("File {} does not contain a valid layout", path.display()),
-                )?;
-                println!(
-                    "Layout: {}",
-                    file.path().file_name().unwrap().to_str().unwrap()
-                );
-                println!("{}", layout);
-                println!("{}", layout.penalize_with_details(corpus));
-            };
-            Ok(())
-        })
-        .collect()
+// Writes the best layout found before an interruption and reports how to resume.
+fn interrupt(config: &Config, best_layout: &Layout, default_output: &str) -> Result<()> {
+    let output = config
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(default_output));
+    best_layout.write_to_file(&output, &config.layout_info.derived())?;
+    println!("Best layout so far:");
+    println!("{}", best_layout);
+    println!("Written to {}", output.display());
+    match &config.checkpoint {
+        Some(path) => Err(format!(
+            "Interrupted. Continue with --checkpoint {} --resume",
+            path.display()
+        )
+        .into()),
+        None => Err("Interrupted".into()),
+    }
+}
+
+pub fn analyze(corpus: &Corpus, inputs: &[String]) -> Result<()> {
+    let results = penalize_files(corpus, analyze_paths(inputs)?);
+    if results.is_empty() {
+        return Err("No layouts to analyze".into());
+    }
+
+    let width = results
+        .iter()
+        .map(|(path, _)| path.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("Layout".len());
+    // With bootstrapping, intervals of the scaled penalty (on the held-out part
+    // if there is one) and of its difference to the first layout.
+    let held_out = corpus.held_out.is_some();
+    let bootstrap = !sampling::comparison_resamples(&results[0].1).is_empty();
+    let mut header = format!("{:>4}  {:width$}  {:>14}  {:>10}", "Rank", "Layout", "Total", "Scaled", width = width);
+    if held_out {
+        header.push_str(&format!("  {:>10}", "Held-out"));
+    }
+    if bootstrap {
+        header.push_str(&format!("  {:>18}  {:>18}", "95% CI", "Diff. to #1"));
+    }
+    println!("{}", header);
+    let first = sampling::comparison_resamples(&results[0].1).to_vec();
+    for (rank, (path, penalty)) in results.iter().enumerate() {
+        let mut row = format!(
+            "{:>4}  {:width$}  {:>14}  {:>10.4}",
+            rank + 1,
+            path.display().to_string(),
+            penalty.total as i64,
+            penalty.scaled,
+            width = width
+        );
+        if let Some((scaled, _)) = &penalty.held_out {
+            row.push_str(&format!("  {:>10.4}", scaled));
+        }
+        if bootstrap {
+            let resamples = sampling::comparison_resamples(penalty);
+            let format = |interval: Option<(f64, f64)>| interval.map(sampling::format_interval).unwrap_or_default();
+            row.push_str(&format!(
+                "  {:>18}  {:>18}",
+                format(sampling::interval(resamples)),
+                format(sampling::difference(resamples, &first))
+            ));
+        }
+        println!("{}", row);
+    }
+    Ok(())
+}
+
+// Layout files of the `analyze` inputs, each once, or of the directory
+// 'analyze' in the current path if there are none.
+fn analyze_paths(inputs: &[String]) -> Result<Vec<PathBuf>> {
+    if inputs.is_empty() {
+        let mut path = std::env::current_dir()?;
+        path.push("analyze");
+        if !path.is_dir() {
+            return Err("No layouts given and no directory 'analyze' found in current path".into());
+        }
+        return layout_files(&path.to_string_lossy());
+    }
+    let mut paths = Vec::new();
+    for input in inputs {
+        for path in layout_files(input)? {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    Ok(paths)
+}
+
+// Prints the penalties of the layout files and returns them ranked from the
+// lowest total penalty. Files that can't be read as a layout are skipped with
+// a warning.
+fn penalize_files(corpus: &Corpus, paths: Vec<PathBuf>) -> Vec<(PathBuf, penalty::LayoutPenalty)> {
+    let mut results = Vec::new();
+    for path in paths {
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| import::parse(&s, None).map_err(|e| e.to_string()));
+        let layout = match parsed {
+            Ok((layout, _)) => layout,
+            Err(e) => {
+                eprintln!("Warning: skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let penalty = layout.penalize_with_details(corpus);
+        println!("Layout: {}", path.display());
+        println!("{}", layout);
+        println!("{}", penalty);
+        results.push((path, penalty));
+    }
+    results.sort_by(|a, b| a.1.total.partial_cmp(&b.1.total).unwrap());
+    results
+}
+
+// Files that an `analyze` input stands for: the files directly inside a
+// directory, the matches of a glob, or the file itself. Inputs without any
+// file are skipped with a warning.
+fn layout_files(input: &str) -> Result<Vec<PathBuf>> {
+    let path = PathBuf::from(input);
+    let mut files = if path.is_dir() {
+        std::fs::read_dir(&path)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect()
+    } else if input.contains(&['*', '?', '['][..]) {
+        glob::glob(input)
+            .map_err(|e| format!("Invalid pattern '{}': {}", input, e))?
+            .flatten()
+            .filter(|path| path.is_file())
+            .collect()
+    } else if path.is_file() {
+        vec![path]
+    } else {
+        Vec::new()
+    };
+    if files.is_empty() {
+        eprintln!("Warning: no layout files found for '{}'", input);
+    }
+    files.sort();
+    Ok(files)
 }
 
 pub fn run_refs(corpus: &penalty::Corpus, config: &app::Config) -> Result<()> {
-    let penalize_and_print = |name, layout: &layout::Layout| {
-        println!("");
+    // With `--svg`, every layout gets its own heatmap next to the given path.
+    let penalize_and_print = |name: &str, layout: &layout::Layout| -> Result<()> {
+        println!();
         let penalty = layout.penalize_with_details(corpus);
         println!("Reference: {}", name);
         println!("{}", layout);
         println!("{}", penalty);
+        if let Some(options) = &config.svg {
+            let options = options.for_layout(name);
+            svg::write_to_file(layout, &penalty, &options)?;
+            println!("Heatmap written to {}", options.path.display());
+        }
+        Ok(())
     };
 
-    penalize_and_print("QWERTY", &layout::QWERTY_LAYOUT);
-    // penalize_and_print("DVORAK",&layout::DVORAK_LAYOUT);
-    penalize_and_print("COLEMAK", &layout::COLEMAK_LAYOUT);
-    penalize_and_print("COLEMAK-DH", &layout::COLEMAK_DH_LAYOUT);
-    // penalize_and_print("QGMLWY", &layout::QGMLWY_LAYOUT);
-    penalize_and_print("WORKMAN", &layout::WORKMAN_LAYOUT);
-    // penalize_and_print("MALTRON",&layout::MALTRON_LAYOUT);
-    penalize_and_print("MTGAP", &layout::MTGAP_LAYOUT);
-    // penalize_and_print("CAPEWELL",&layout::CAPEWELL_LAYOUT);
-    // penalize_and_print("ARENSITO",&layout::ARENSITO_LAYOUT);
-    penalize_and_print("RSTHD", &layout::RSTHD_LAYOUT);
-    penalize_and_print("INIT", &config.layout);
+    for entry in config.registry.entries() {
+        penalize_and_print(&entry.name, &entry.layout)?;
+    }
+    penalize_and_print("INIT", &config.layout)
+}
+
+fn write_svg(config: &Config, layout: &Layout, penalty: &penalty::LayoutPenalty) -> Result<()> {
+    if let Some(options) = &config.svg {
+        svg::write_to_file(layout, penalty, options)?;
+        println!("Heatmap written to {}", options.path.display());
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{COLEMAK_DH_LAYOUT, QWERTY_LAYOUT};
+    use crate::layout_file::LayoutInfo;
+    use crate::ngrams;
+    use std::fs;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keygen-simulator-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<String> {
+        paths.iter().map(|path| path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn analyze_inputs() {
+        let dir = temp_dir("inputs");
+        for name in &["b.layout", "a.layout", "c.txt"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("nested/d.layout"), "").unwrap();
+        let input = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        assert_eq!(names(&layout_files(&input("")).unwrap()), ["a.layout", "b.layout", "c.txt"]);
+        assert_eq!(names(&layout_files(&input("*.layout")).unwrap()), ["a.layout", "b.layout"]);
+        assert_eq!(names(&layout_files(&input("c.txt")).unwrap()), ["c.txt"]);
+        assert!(layout_files(&input("missing.layout")).unwrap().is_empty());
+        assert!(layout_files(&input("missing*")).unwrap().is_empty());
+        assert!(layout_files(&input("[")).is_err());
+
+        // Files are analyzed once, in the order of the inputs.
+        let inputs = [input("c.txt"), input("*.layout"), input("nested"), input("a.layout")];
+        assert_eq!(names(&analyze_paths(&inputs).unwrap()), ["c.txt", "a.layout", "b.layout", "d.layout"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ranking() {
+        let dir = temp_dir("ranking");
+        QWERTY_LAYOUT.write_to_file(&dir.join("qwerty.layout"), &LayoutInfo::default()).unwrap();
+        COLEMAK_DH_LAYOUT.write_to_file(&dir.join("colemak-dh.layout"), &LayoutInfo::default()).unwrap();
+        fs::write(dir.join("broken.layout"), "not a layout").unwrap();
+
+        let corpus = ngrams::parse("the \t10\nand \t5\n", false, 4).unwrap();
+        let paths = layout_files(&dir.to_string_lossy()).unwrap();
+        assert_eq!(paths.len(), 3);
+        let results = penalize_files(&corpus, paths);
+        let ranked: Vec<PathBuf> = results.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(names(&ranked), ["colemak-dh.layout", "qwerty.layout"]);
+        assert!(results[0].1.total < results[1].1.total);
+        assert_eq!(results[1].1.total, QWERTY_LAYOUT.penalize_with_details(&corpus).total);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
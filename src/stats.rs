@@ -0,0 +1,146 @@
+/// The `corpus-stats` command: what a corpus consists of, and how it differs
+/// from another one.
+use std::collections::HashMap;
+use std::path::Path;
+
+use num_format::{Buffer, Locale};
+
+use crate::app::Config;
+use crate::ingest::{self, TextStats};
+use crate::ngrams;
+use crate::penalty::{Corpus, INIT_LAYOUT};
+use crate::sampling::Sampling;
+use crate::Result;
+
+const TOP_CHARS: usize = 40;
+const TOP_NGRAMS: usize = 20;
+const TOP_DIFFERENCES: usize = 15;
+
+struct Summary {
+    name: String,
+    corpus: Corpus,
+    // N-grams of the text, including those the layout can't type. Unknown
+    // for n-gram tables.
+    text: Option<TextStats>,
+    // Length of the n-grams.
+    context: usize,
+}
+
+impl Summary {
+    fn read(path: &Path, config: &Config) -> Result<Summary> {
+        let name = path.display().to_string();
+        if ngrams::is_table(path) {
+            let corpus = ngrams::read_corpus(path, false, &config.preprocess, &Sampling::default(), config.context)?;
+            return Ok(Summary { name, corpus, text: None, context: config.context });
+        }
+        let (corpus, stats) = ingest::read_path_with_stats(path, &config.preprocess, config.context)?;
+        Ok(Summary { name, corpus, text: Some(stats), context: config.context })
+    }
+
+    // Relative frequency of the last `n` characters of the n-grams.
+    fn ngrams(&self, n: usize) -> HashMap<&str, f64> {
+        let mut ngrams = HashMap::new();
+        for (ngram, count) in &self.corpus.ngrams {
+            let s = ngram.as_str();
+            *ngrams.entry(&s[s.len() - n..]).or_insert(0.0) += count / self.corpus.len;
+        }
+        ngrams
+    }
+
+    fn char_frequencies(&self) -> HashMap<char, f64> {
+        let total: f64 = self.corpus.chars.values().sum();
+        self.corpus.chars.iter().map(|(c, count)| (*c, count / total)).collect()
+    }
+
+    fn print(&self) {
+        println!("Corpus: {}", self.name);
+        println!(
+            "{}-grams: {} total, {} unique",
+            self.context,
+            thousands(self.corpus.len),
+            thousands(self.corpus.ngrams.len() as f64)
+        );
+        match &self.text {
+            Some(text) if text.ngrams > 0.0 => println!(
+                "Dropped: {:.2}% of {} n-grams contain characters the default layout can't type",
+                100.0 * text.dropped / text.ngrams,
+                thousands(text.ngrams)
+            ),
+            _ => println!("Dropped: unknown for n-gram tables"),
+        }
+
+        let position_map = INIT_LAYOUT.get_position_map();
+        println!("\nCharacters:");
+        for (c, freq) in top(self.char_frequencies(), TOP_CHARS) {
+            let note = if position_map.get_key_position(c).is_none() { "  (can't be typed)" } else { "" };
+            println!("  {:<8} {:>7.3}%{}", format!("{:?}", c), 100.0 * freq, note);
+        }
+        for (n, name) in [(2, "Bigrams"), (3, "Trigrams"), (4, "Quadgrams")].iter() {
+            println!("\n{}:", name);
+            for (ngram, freq) in top(self.ngrams(*n), TOP_NGRAMS) {
+                println!("  {:<8} {:>7.3}%", format!("{:?}", ngram), 100.0 * freq);
+            }
+        }
+    }
+}
+
+// The `n` entries with the highest value.
+fn top<K: Ord>(map: HashMap<K, f64>, n: usize) -> Vec<(K, f64)> {
+    let mut entries: Vec<(K, f64)> = map.into_iter().collect();
+    entries.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+    entries.truncate(n);
+    entries
+}
+
+fn thousands(n: f64) -> String {
+    let mut buf = Buffer::default();
+    buf.write_formatted(&(n.round() as i64), &Locale::en);
+    buf.to_string()
+}
+
+// Prints the keys whose relative frequency differs most between `a` and `b`.
+fn print_differences<K>(name: &str, a: &HashMap<K, f64>, b: &HashMap<K, f64>)
+where
+    K: std::hash::Hash + Eq + Ord + std::fmt::Debug,
+{
+    let mut keys: Vec<&K> = a.keys().chain(b.keys().filter(|k| !a.contains_key(k))).collect();
+    let freq = |map: &HashMap<K, f64>, k: &K| map.get(k).cloned().unwrap_or(0.0);
+    keys.sort_by(|x, y| {
+        let dx = (freq(a, x) - freq(b, x)).abs();
+        let dy = (freq(a, y) - freq(b, y)).abs();
+        dy.partial_cmp(&dx).unwrap().then(x.cmp(y))
+    });
+    println!("\n{}:", name);
+    for k in keys.into_iter().take(TOP_DIFFERENCES) {
+        let (fa, fb) = (freq(a, k), freq(b, k));
+        println!(
+            "  {:<8} {:>7.3}% {:>7.3}% {:>+8.3}",
+            format!("{:?}", k),
+            100.0 * fa,
+            100.0 * fb,
+            100.0 * (fa - fb)
+        );
+    }
+}
+
+pub fn run(config: &Config) -> Result<()> {
+    let (path, other) = match (&config.corpora[..], &config.inputs[..]) {
+        ([(path, _)], []) => (path.as_path(), None),
+        ([(path, _)], [other]) => (path.as_path(), Some(Path::new(other))),
+        _ => return Err("'corpus-stats' takes a corpus and optionally a second one to compare it with".into()),
+    };
+
+    let a = Summary::read(path, config)?;
+    a.print();
+    if let Some(other) = other {
+        let b = Summary::read(other, config)?;
+        println!();
+        b.print();
+
+        println!("\nLargest differences in relative frequency (percentage points):");
+        println!("  {:<8} {:>8} {:>8} {:>8}", "", "first", "second", "diff");
+        print_differences("Characters", &a.char_frequencies(), &b.char_frequencies());
+        print_differences("Bigrams", &a.ngrams(2), &b.ngrams(2));
+    }
+    Ok(())
+}
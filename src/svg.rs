@@ -0,0 +1,305 @@
+/// Renders keyboard layouts as SVG heatmaps on the keygen key geometry.
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use crate::layout::{Finger, Layout, KEY_FINGERS, KEY_GEOMETRY};
+use crate::penalty::LayoutPenalty;
+use crate::Result;
+
+const KEY_SIZE: f64 = 54.0;
+const KEY_GAP: f64 = 4.0;
+const MARGIN: f64 = 16.0;
+const LEGEND_HEIGHT: f64 = 48.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Usage,
+    Penalty,
+}
+
+impl Metric {
+    pub fn from_name(s: &str) -> Option<Metric> {
+        match s {
+            "usage" => Some(Metric::Usage),
+            "penalty" => Some(Metric::Penalty),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Metric::Usage => "key usage",
+            Metric::Penalty => "penalty contribution",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SvgOptions {
+    pub path: PathBuf,
+    pub metric: Metric,
+    pub fingers: bool,
+}
+
+impl SvgOptions {
+    /// Options for the heatmap of one of several layouts, written next to
+    /// `path` with the layout name appended to the file stem.
+    pub fn for_layout(&self, name: &str) -> SvgOptions {
+        let name: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c.to_ascii_lowercase() } else { '-' })
+            .collect();
+        let stem = self.path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut file_name = format!("{}-{}", stem, name);
+        if let Some(extension) = self.path.extension() {
+            file_name.push('.');
+            file_name.push_str(&extension.to_string_lossy());
+        }
+        SvgOptions {
+            path: self.path.with_file_name(file_name),
+            metric: self.metric,
+            fingers: self.fingers,
+        }
+    }
+}
+
+pub fn write_to_file(layout: &Layout, penalty: &LayoutPenalty, options: &SvgOptions) -> Result<()> {
+    let svg = render(layout, penalty, options.metric, options.fingers);
+    std::fs::write(&options.path, svg)?;
+    Ok(())
+}
+
+pub fn render(layout: &Layout, penalty: &LayoutPenalty, metric: Metric, fingers: bool) -> String {
+    let values = match metric {
+        Metric::Usage => &penalty.key_usage.0,
+        Metric::Penalty => &penalty.key_penalty.0,
+    };
+    let sum: f64 = values.iter().map(|v| v.max(0.0)).sum();
+    let max = values.iter().cloned().fold(0.0, f64::max);
+
+    let (cols, rows) = KEY_GEOMETRY
+        .0
+        .iter()
+        .fold((0.0f64, 0.0f64), |(w, h), (x, y)| (w.max(x + 1.0), h.max(y + 1.0)));
+    let width = 2.0 * MARGIN + cols * KEY_SIZE;
+    let height = 2.0 * MARGIN + rows * KEY_SIZE + LEGEND_HEIGHT;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif">"#,
+        w = width,
+        h = height
+    );
+    let _ = writeln!(svg, "<title>Keyboard heatmap ({})</title>", metric.name());
+    let _ = writeln!(
+        svg,
+        r##"<rect x="0" y="0" width="{}" height="{}" fill="#ffffff"/>"##,
+        width, height
+    );
+
+    let lower = layout.lower();
+    let upper = layout.upper();
+    for pos in 0..36 {
+        let (kx, ky) = KEY_GEOMETRY.0[pos];
+        let x = MARGIN + kx * KEY_SIZE;
+        let y = MARGIN + ky * KEY_SIZE;
+        let size = KEY_SIZE - KEY_GAP;
+
+        if is_placeholder(lower[pos]) && is_placeholder(upper[pos]) {
+            let _ = writeln!(
+                svg,
+                r##"<rect x="{}" y="{}" width="{}" height="{}" rx="6" fill="none" stroke="#bbbbbb" stroke-dasharray="4 3"/>"##,
+                x, y, size, size
+            );
+            continue;
+        }
+
+        let t = if max > 0.0 { values[pos].max(0.0) / max } else { 0.0 };
+        let _ = writeln!(
+            svg,
+            r##"<rect x="{}" y="{}" width="{}" height="{}" rx="6" fill="{}" stroke="#555555"/>"##,
+            x,
+            y,
+            size,
+            size,
+            heat_colour(t)
+        );
+
+        if fingers {
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{}" y="{}" width="{}" height="5" rx="2" fill="{}"/>"#,
+                x + 4.0,
+                y + size - 8.0,
+                size - 8.0,
+                finger_colour(KEY_FINGERS.0[pos])
+            );
+        }
+
+        if !is_placeholder(lower[pos]) {
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" font-size="20" text-anchor="middle">{}</text>"#,
+                x + size / 2.0,
+                y + size / 2.0 + 7.0,
+                label(lower[pos])
+            );
+        }
+        if !is_placeholder(upper[pos]) && upper[pos] != lower[pos] {
+            let _ = writeln!(
+                svg,
+                r##"<text x="{}" y="{}" font-size="11" fill="#444444">{}</text>"##,
+                x + 4.0,
+                y + 12.0,
+                label(upper[pos])
+            );
+        }
+        if sum > 0.0 {
+            let _ = writeln!(
+                svg,
+                r##"<text x="{}" y="{}" font-size="9" text-anchor="end" fill="#444444">{:.1}%</text>"##,
+                x + size - 3.0,
+                y + 11.0,
+                100.0 * values[pos].max(0.0) / sum
+            );
+        }
+    }
+
+    let legend_y = height - MARGIN - LEGEND_HEIGHT + 16.0;
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" font-size="12">Colour: {} (darkest = {:.4})</text>"#,
+        MARGIN,
+        legend_y + 4.0,
+        metric.name(),
+        max
+    );
+    if fingers {
+        use Finger::*;
+        let legend_y = legend_y + 22.0;
+        let mut x = MARGIN;
+        for finger in [Thumb, Index, Middle, Ring, Pinky].iter() {
+            // Folded from 0.0 as an empty f64 sum is -0.0.
+            let finger_usage = penalty
+                .usage
+                .values()
+                .filter_map(|hand_usage| hand_usage.get(finger))
+                .fold(0.0, |sum, usage| sum + usage);
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{}" y="{}" width="12" height="12" fill="{}"/><text x="{}" y="{}" font-size="12">{} {:.1}%</text>"#,
+                x,
+                legend_y - 6.0,
+                finger_colour(*finger),
+                x + 16.0,
+                legend_y + 4.0,
+                finger,
+                100.0 * finger_usage
+            );
+            x += 100.0;
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn is_placeholder(c: char) -> bool {
+    c == '\0' || c == '*'
+}
+
+fn label(c: char) -> String {
+    match c {
+        ' ' => "␣".to_string(),
+        '&' => "&amp;".to_string(),
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        '"' => "&quot;".to_string(),
+        '\'' => "&apos;".to_string(),
+        c => c.to_string(),
+    }
+}
+
+// White for unused keys, through yellow to red for the most used key.
+fn heat_colour(t: f64) -> String {
+    let t = t.clamp(0.0, 1.0);
+    let (from, to, t) = if t < 0.5 {
+        ((255.0, 255.0, 255.0), (255.0, 214.0, 90.0), t * 2.0)
+    } else {
+        ((255.0, 214.0, 90.0), (214.0, 48.0, 39.0), (t - 0.5) * 2.0)
+    };
+    let mix = |a: f64, b: f64| (a + (b - a) * t).round() as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        mix(from.0, to.0),
+        mix(from.1, to.1),
+        mix(from.2, to.2)
+    )
+}
+
+fn finger_colour(finger: Finger) -> &'static str {
+    match finger {
+        Finger::Thumb => "#7f7f7f",
+        Finger::Index => "#1f77b4",
+        Finger::Middle => "#2ca02c",
+        Finger::Ring => "#9467bd",
+        Finger::Pinky => "#ff7f0e",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::QWERTY_LAYOUT;
+    use crate::ngrams;
+
+    fn qwerty(metric: Metric, fingers: bool) -> String {
+        // All usage is attributed to the 't' of the single n-gram.
+        let corpus = ngrams::parse("the \t10\n", false, 4).unwrap();
+        let penalty = QWERTY_LAYOUT.penalize_with_details(&corpus);
+        render(&QWERTY_LAYOUT, &penalty, metric, fingers)
+    }
+
+    #[test]
+    fn keys_and_labels() {
+        let svg = qwerty(Metric::Usage, false);
+        assert!(svg.starts_with("<svg ") && svg.ends_with("</svg>\n"));
+        assert!(svg.contains("<title>Keyboard heatmap (key usage)</title>"));
+        assert_eq!(svg.matches(r##"stroke="#555555""##).count(), 31);
+        assert_eq!(svg.matches("stroke-dasharray").count(), 5);
+        for label in &[">q<", ">Q<", ">;<", ">:<", ">/<", ">?<", ">␣<", ">&lt;<", ">&gt;<"] {
+            assert_eq!(svg.matches(label).count(), 1, "{}", label);
+        }
+        assert_eq!(svg.matches("font-size=\"20\"").count(), 31);
+        // Space has the same character on both layers.
+        assert_eq!(svg.matches("font-size=\"11\"").count(), 30);
+        assert!(!svg.contains("finger"));
+    }
+
+    #[test]
+    fn usage_colours() {
+        let svg = qwerty(Metric::Usage, false);
+        assert_eq!(svg.matches("100.0%").count(), 1);
+        assert_eq!(svg.matches("0.0%").count(), 31);
+        assert_eq!(svg.matches(r##"fill="#d63027""##).count(), 1);
+        assert_eq!(svg.matches(r##"fill="#ffffff" stroke"##).count(), 30);
+    }
+
+    #[test]
+    fn finger_overlay() {
+        let svg = qwerty(Metric::Penalty, true);
+        assert!(svg.contains("<title>Keyboard heatmap (penalty contribution)</title>"));
+        assert_eq!(svg.matches(r#"height="5""#).count(), 31);
+        assert!(svg.contains("Index 100.0%"));
+        assert!(svg.contains("Pinky 0.0%"));
+    }
+
+    #[test]
+    fn layout_paths() {
+        let options = SvgOptions { path: PathBuf::from("out/heatmap.svg"), metric: Metric::Usage, fingers: false };
+        assert_eq!(options.for_layout("QWERTY").path, PathBuf::from("out/heatmap-qwerty.svg"));
+        assert_eq!(options.for_layout("Colemak DH").path, PathBuf::from("out/heatmap-colemak-dh.svg"));
+        let options = SvgOptions { path: PathBuf::from("heatmap"), ..options };
+        assert_eq!(options.for_layout("INIT").path, PathBuf::from("heatmap-init"));
+    }
+}
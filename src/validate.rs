@@ -0,0 +1,234 @@
+/// Sanity checks for layouts, run by the `validate` command and whenever a
+/// layout is loaded.
+use std::fmt;
+
+use crate::layout::Layout;
+use crate::penalty::Corpus;
+use crate::Result;
+
+pub enum Issue {
+    // A character on more than one key, with the layer (`true` for upper) and
+    // position of each.
+    Duplicate(char, Vec<(bool, usize)>),
+    MissingLetter(char),
+    // A key whose lower and upper characters aren't the two cases of a letter.
+    CaseMismatch(usize, char, char),
+    // A character used by the corpus that the layout can't type, with its
+    // share of the characters of the corpus.
+    Untypable(char, f64),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::Duplicate(c, keys) => {
+                let keys: Vec<String> = keys
+                    .iter()
+                    .map(|(upper, pos)| format!("{} {}", if *upper { "upper" } else { "lower" }, pos))
+                    .collect();
+                write!(f, "{:?} is on more than one key ({})", c, keys.join(", "))
+            }
+            Issue::MissingLetter(c) => write!(f, "letter {:?} is missing", c),
+            Issue::CaseMismatch(pos, lower, upper) => write!(
+                f,
+                "key {} has {:?} and {:?}, which are not the cases of one letter",
+                pos, lower, upper
+            ),
+            Issue::Untypable(c, share) => {
+                write!(f, "{:?} can't be typed but makes up {} of the corpus's characters", c, percent(*share))
+            }
+        }
+    }
+}
+
+fn percent(share: f64) -> String {
+    if share > 0.0 && share < 0.0001 {
+        "less than 0.01%".to_string()
+    } else {
+        format!("{:.2}%", 100.0 * share)
+    }
+}
+
+fn is_placeholder(c: char) -> bool {
+    c == '*' || c == '\0'
+}
+
+/// Checks a layout on its own.
+pub fn check(layout: &Layout) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let lower = layout.lower();
+    let upper = layout.upper();
+
+    // Keys with the same character on both layers, like space, count once.
+    let mut keys: Vec<(char, Vec<(bool, usize)>)> = Vec::new();
+    for pos in 0..36 {
+        for (is_upper, c) in [(false, lower[pos]), (true, upper[pos])].iter() {
+            if is_placeholder(*c) || (*is_upper && upper[pos] == lower[pos]) {
+                continue;
+            }
+            match keys.iter_mut().find(|(k, _)| k == c) {
+                Some((_, found)) => found.push((*is_upper, pos)),
+                None => keys.push((*c, vec![(*is_upper, pos)])),
+            }
+        }
+    }
+    issues.extend(
+        keys.into_iter()
+            .filter(|(_, found)| found.len() > 1)
+            .map(|(c, found)| Issue::Duplicate(c, found)),
+    );
+
+    issues.extend(
+        ('a'..='z')
+            .filter(|c| !lower.contains(c) && !upper.contains(c) && !upper.contains(&c.to_ascii_uppercase()))
+            .map(Issue::MissingLetter),
+    );
+
+    for pos in 0..36 {
+        let (l, u) = (lower[pos], upper[pos]);
+        let letter = (l.is_alphabetic() && l.is_lowercase()) || (u.is_alphabetic() && u.is_uppercase());
+        let cases_match = l.to_uppercase().eq(std::iter::once(u)) && u.to_lowercase().eq(std::iter::once(l));
+        if letter && !cases_match {
+            issues.push(Issue::CaseMismatch(pos, l, u));
+        }
+    }
+    issues
+}
+
+/// Checks that the layout can type every character that the text of the
+/// corpus uses. Characters are typable if penalties see them, i.e. if they
+/// are in the layout's position map.
+pub fn check_corpus(layout: &Layout, corpus: &Corpus) -> Vec<Issue> {
+    let position_map = layout.get_position_map();
+    let total: f64 = corpus.chars.values().sum();
+    let mut untypable: Vec<(char, f64)> = corpus
+        .chars
+        .iter()
+        .filter(|(c, _)| position_map.get_key_position(**c).is_none())
+        .map(|(c, count)| (*c, count / total))
+        .collect();
+    untypable.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+    untypable
+        .into_iter()
+        .map(|(c, share)| Issue::Untypable(c, share))
+        .collect()
+}
+
+/// Prints every issue as a warning on stderr.
+pub fn warn(issues: &[Issue]) {
+    for issue in issues {
+        eprintln!("Warning: {}", issue);
+    }
+}
+
+/// Prints a single warning for all characters of the corpus that the layout
+/// can't type, as there are usually a few.
+pub fn warn_corpus(layout: &Layout, corpus: &Corpus) {
+    let untypable: Vec<(char, f64)> = check_corpus(layout, corpus)
+        .into_iter()
+        .filter_map(|issue| match issue {
+            Issue::Untypable(c, share) => Some((c, share)),
+            _ => None,
+        })
+        .collect();
+    if untypable.is_empty() {
+        return;
+    }
+    let chars: Vec<String> = untypable.iter().map(|(c, _)| format!("{:?}", c)).collect();
+    eprintln!(
+        "Warning: {} of the corpus's characters can't be typed: {} (see `validate`)",
+        percent(untypable.iter().map(|(_, share)| share).sum()),
+        chars.join(" ")
+    );
+}
+
+/// The `validate` command: prints all issues and fails if there are any.
+pub fn run(layout: &Layout, corpus: Option<&Corpus>) -> Result<()> {
+    let mut issues = check(layout);
+    if let Some(corpus) = corpus {
+        issues.extend(check_corpus(layout, corpus));
+    }
+    if issues.is_empty() {
+        println!("No issues found.");
+        return Ok(());
+    }
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    Err(format!("Found {} issue(s) with the layout", issues.len()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::QWERTY_LAYOUT;
+
+    fn messages(issues: &[Issue]) -> Vec<String> {
+        issues.iter().map(|issue| issue.to_string()).collect()
+    }
+
+    #[test]
+    fn reference_layout() {
+        assert!(check(&QWERTY_LAYOUT).is_empty());
+    }
+
+    #[test]
+    fn duplicates_and_case_mismatches() {
+        let mut lower = *QWERTY_LAYOUT.lower();
+        let upper = *QWERTY_LAYOUT.upper();
+        lower[0] = 'e';
+        let issues = check(&Layout::from_layers(lower, upper));
+        match &issues[0] {
+            Issue::Duplicate('e', keys) => assert_eq!(keys, &[(false, 0), (false, 2)]),
+            _ => panic!("expected a duplicate"),
+        }
+        assert_eq!(
+            messages(&issues),
+            [
+                "'e' is on more than one key (lower 0, lower 2)",
+                "key 0 has 'e' and 'Q', which are not the cases of one letter",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_letters() {
+        let mut lower = *QWERTY_LAYOUT.lower();
+        let mut upper = *QWERTY_LAYOUT.upper();
+        lower[0] = '*';
+        upper[0] = '*';
+        // A letter only on the upper layer isn't missing.
+        lower[1] = '!';
+        assert_eq!(
+            messages(&check(&Layout::from_layers(lower, upper))),
+            ["letter 'q' is missing", "key 1 has '!' and 'W', which are not the cases of one letter"]
+        );
+    }
+
+    #[test]
+    fn untypable_shares() {
+        let mut corpus = Corpus {
+            chars: vec![('a', 8999.5), ('é', 0.5), ('\n', 1000.0), ('s', 0.0)].into_iter().collect(),
+            ..Corpus::default()
+        };
+        let issues = check_corpus(&QWERTY_LAYOUT, &corpus);
+        assert_eq!(
+            messages(&issues),
+            [
+                "'\\n' can't be typed but makes up 10.00% of the corpus's characters",
+                "'é' can't be typed but makes up less than 0.01% of the corpus's characters",
+            ]
+        );
+        match issues[1] {
+            Issue::Untypable('é', share) => assert_eq!(share, 0.00005),
+            _ => panic!("expected an untypable character"),
+        }
+        assert_eq!(
+            run(&QWERTY_LAYOUT, Some(&corpus)).unwrap_err().to_string(),
+            "Found 2 issue(s) with the layout"
+        );
+        corpus.chars.remove(&'é');
+        corpus.chars.remove(&'\n');
+        assert!(run(&QWERTY_LAYOUT, Some(&corpus)).is_ok());
+    }
+}